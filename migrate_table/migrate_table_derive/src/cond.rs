@@ -0,0 +1,99 @@
+use proc_macro2::TokenStream;
+use quote::{quote, quote_spanned};
+use syn::parse::{Parse, ParseStream};
+use syn::spanned::Spanned;
+use syn::{BinOp, Expr, UnOp};
+
+/// `Model, condition`
+pub struct CondInput {
+    model: syn::Path,
+    condition: Expr,
+}
+
+impl Parse for CondInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let model = input.parse()?;
+        input.parse::<syn::Token![,]>()?;
+        let condition = input.parse()?;
+        Ok(CondInput { model, condition })
+    }
+}
+
+pub fn expand(input: CondInput) -> TokenStream {
+    match condition(&input.model, &input.condition) {
+        Ok(tokens) => tokens,
+        Err(error) => error.to_compile_error(),
+    }
+}
+
+fn condition(model: &syn::Path, expr: &Expr) -> syn::Result<TokenStream> {
+    match expr {
+        Expr::Paren(paren) => condition(model, &paren.expr),
+        Expr::Group(group) => condition(model, &group.expr),
+        Expr::Unary(unary) => match unary.op {
+            UnOp::Not(_) => {
+                let inner = condition(model, &unary.expr)?;
+                Ok(quote!(!(#inner)))
+            }
+            _ => Err(unsupported(expr)),
+        },
+        Expr::Binary(binary) => {
+            let method = match binary.op {
+                BinOp::And(_) | BinOp::Or(_) => {
+                    let left = condition(model, &binary.left)?;
+                    let right = condition(model, &binary.right)?;
+                    let method = match binary.op {
+                        BinOp::And(_) => quote!(and),
+                        _ => quote!(or),
+                    };
+                    return Ok(quote!(::rsorm::Condition::#method(#left, #right)));
+                }
+                BinOp::Eq(_) => quote!(eq),
+                BinOp::Ne(_) => quote!(ne),
+                BinOp::Lt(_) => quote!(lt),
+                BinOp::Le(_) => quote!(le),
+                BinOp::Gt(_) => quote!(gt),
+                BinOp::Ge(_) => quote!(ge),
+                _ => return Err(unsupported(expr)),
+            };
+            let column = column(model, &binary.left)?;
+            let value = &binary.right;
+            // a value of the wrong type is reported on the value
+            Ok(quote_spanned!(value.span()=> #column.#method(#value)))
+        }
+        // `name.like("J%")`, `age.is_null()`, `age.in_(vec![1, 2])` and the like
+        Expr::MethodCall(call) => {
+            let column = column(model, &call.receiver)?;
+            let method = &call.method;
+            let args = &call.args;
+            Ok(quote!(#column.#method(#args)))
+        }
+        _ => Err(unsupported(expr)),
+    }
+}
+
+/// the model's typed column constant, spanned at the column so a typo is reported there
+fn column(model: &syn::Path, expr: &Expr) -> syn::Result<TokenStream> {
+    if let Expr::Path(path) = expr {
+        if path.qself.is_none()
+            && path.path.leading_colon.is_none()
+            && path.path.segments.len() == 1
+        {
+            let ident = &path.path.segments[0].ident;
+            let constant = syn::Ident::new(&ident.to_string().to_uppercase(), ident.span());
+            return Ok(quote!(#model::#constant));
+        }
+    }
+    Err(syn::Error::new(
+        expr.span(),
+        "expected a column of the model, like `age`",
+    ))
+}
+
+fn unsupported(expr: &Expr) -> syn::Error {
+    syn::Error::new(
+        expr.span(),
+        "unsupported condition, expected a comparison like `age > 18`, a column method like \
+         `name.like(\"J%\")`, or conditions combined with `&&`, `||` and `!`",
+    )
+}
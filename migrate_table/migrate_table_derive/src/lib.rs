@@ -1,13 +1,18 @@
 #![recursion_limit = "1024"]
 extern crate proc_macro;
 
+mod cond;
+
 use proc_macro::TokenStream;
 use quote::quote;
 use syn;
 
 /// A macro to generate a schema that describes the type deriving the macro.
 /// The schema is passed to DB's create_table() method which interacts with sqlite
-/// 
+///
+/// Also generates a typed `rsorm::Column` constant for each field, named after the field
+/// in upper case, for building conditions that are checked at compile time.
+///
 /// This implementation pattern closely follows a pattern from the Rust
 /// docs: https://doc.rust-lang.org/book/ch19-06-macros.html#how-to-write-a-custom-derive-macro
 #[proc_macro_derive(MigrateTable)]
@@ -21,19 +26,27 @@ fn impl_migrate_table(ast: &syn::DeriveInput) -> TokenStream {
     let name = &ast.ident;
     let data = &ast.data;
     let fields = field_names(data).expect("ERROR: rsorm can only migrate structs");
+    let idents = fields.iter().map(|f| {
+        f.ident
+            .as_ref()
+            .expect("ERROR: rsorm can only migrate named fields")
+            .to_string()
+    });
+    let types = fields.iter().map(|f| {
+        let ty = &f.ty;
+        quote!(#ty).to_string().replace(' ', "")
+    });
+    let columns = column_constants(name, &fields);
     let gen = quote! {
+        #columns
+
         impl MigrateTable for #name {
             /// Generates a database schema in the form of a tuple. <br>
             /// Name is the name of the struct/table.<br>
             /// Called like: `**struct_name**::generate_schema()`
             fn generate_schema() -> (String, Vec<(String, String)>) {
                 let name = String::from(stringify!(#name));
-                let field_str = stringify!(#fields);
-                let fs = field_str.split(" ").collect::<Vec<&str>>();
-                let mut field_tups = Vec::new();
-                for i in (3..fs.len()).step_by(4) {
-                    field_tups.push((String::from(fs[i-2]), String::from(fs[i])));
-                }
+                let field_tups = vec![#((String::from(#idents), String::from(#types))),*];
                 (name, field_tups)
             }
         }
@@ -41,6 +54,89 @@ fn impl_migrate_table(ast: &syn::DeriveInput) -> TokenStream {
     gen.into()
 }
 
+/// Generates an `rsorm::FromRow` impl that reads each field from the column of the same name.
+/// An alternative to deriving serde's `Deserialize` for models that can't or shouldn't use serde.
+/// A model should derive one or the other, not both.
+#[proc_macro_derive(FromRow)]
+pub fn from_row_derive(input: TokenStream) -> TokenStream {
+    let ast: syn::DeriveInput = syn::parse(input).unwrap();
+    let name = &ast.ident;
+    let fields = field_names(&ast.data).expect("ERROR: rsorm can only derive FromRow for structs");
+    let idents: Vec<&syn::Ident> = fields.iter().filter_map(|f| f.ident.as_ref()).collect();
+    let columns: Vec<String> = idents.iter().map(|i| i.to_string()).collect();
+    let gen = quote! {
+        impl ::rsorm::FromRow for #name {
+            fn from_row(row: &::rsorm::RowRef) -> ::rsorm::Result<Self> {
+                Ok(#name {
+                    #(#idents: row.get(#columns)?,)*
+                })
+            }
+        }
+    };
+    gen.into()
+}
+
+/// Generates an `rsorm::ToRow` impl that writes each field to the column of the same name.
+/// An alternative to deriving serde's `Serialize` for models that can't or shouldn't use serde.
+/// A model should derive one or the other, not both.
+#[proc_macro_derive(ToRow)]
+pub fn to_row_derive(input: TokenStream) -> TokenStream {
+    let ast: syn::DeriveInput = syn::parse(input).unwrap();
+    let name = &ast.ident;
+    let fields = field_names(&ast.data).expect("ERROR: rsorm can only derive ToRow for structs");
+    let idents: Vec<&syn::Ident> = fields.iter().filter_map(|f| f.ident.as_ref()).collect();
+    let columns: Vec<String> = idents.iter().map(|i| i.to_string()).collect();
+    // a repetition can't use the same variable twice
+    let errors = columns.clone();
+    let gen = quote! {
+        impl ::rsorm::ToRow for #name {
+            fn to_row(&self) -> ::rsorm::Result<Vec<(String, ::rsorm::Value)>> {
+                Ok(vec![
+                    #((
+                        String::from(#columns),
+                        ::rsorm::ToValue::to_value(&self.#idents)
+                            .map_err(|e| e.in_column(#errors))?,
+                    ),)*
+                ])
+            }
+        }
+    };
+    gen.into()
+}
+
+/// A condition on a model's columns, checked at compile time and built into an `rsorm::Condition`.<br>
+/// Called like: `cond!(Person, age > 18 && name == n)`.<br>
+/// Columns are compared with `==`, `!=`, `<`, `<=`, `>` and `>=`, or with the column methods
+/// `like`, `is_null`, `is_not_null` and `in_`, and combined with `&&`, `||` and `!`.
+/// Each column resolves to the model's typed column constant generated by `MigrateTable`,
+/// so a misspelled column is reported at the typo and a value of the wrong type at the value.
+/// Values can be any expression and are bound as statement parameters.
+#[proc_macro]
+pub fn cond(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as cond::CondInput);
+    cond::expand(input).into()
+}
+
+fn column_constants(name: &syn::Ident, fields: &syn::Fields) -> proc_macro2::TokenStream {
+    let constants = fields.iter().filter_map(|f| {
+        let ident = f.ident.as_ref()?;
+        let column = ident.to_string();
+        let constant = syn::Ident::new(&column.to_uppercase(), ident.span());
+        let doc = format!("The `{}` column", column);
+        let ty = &f.ty;
+        Some(quote! {
+            #[doc = #doc]
+            pub const #constant: ::rsorm::Column<#name, #ty> = ::rsorm::Column::new(#column);
+        })
+    });
+    quote! {
+        #[allow(dead_code)]
+        impl #name {
+            #(#constants)*
+        }
+    }
+}
+
 fn field_names(data: &syn::Data) -> Result<syn::Fields, &'static str> {
     match data {
         syn::Data::Struct(data) => {
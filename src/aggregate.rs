@@ -0,0 +1,284 @@
+use crate::column::Column;
+use crate::error::{Error, Result};
+use crate::query::{CompareOp, Condition};
+use crate::row::{FromRow, RowRef};
+use crate::sqlgen::Select;
+use crate::value::{FromValue, ToValue};
+use crate::DB;
+use std::marker::PhantomData;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Function {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+///
+/// An aggregate over the selected records, such as `COUNT(*)` or `AVG(age)`.<br>
+/// Used with `Query::aggregate` and `Grouped::aggregate`, and compared with a value
+/// to build a `Having` filter on groups.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aggregate {
+    function: Function,
+    column: Option<String>,
+}
+
+impl Aggregate {
+    fn of<C: AsRef<str>>(function: Function, column: C) -> Aggregate {
+        Aggregate {
+            function,
+            column: Some(column.as_ref().to_string()),
+        }
+    }
+
+    /// `COUNT(*)`, the number of records
+    pub fn count() -> Aggregate {
+        Aggregate {
+            function: Function::Count,
+            column: None,
+        }
+    }
+
+    /// `COUNT(column)`, the number of records where `column` isn't null
+    pub fn count_of<C: AsRef<str>>(column: C) -> Aggregate {
+        Aggregate::of(Function::Count, column)
+    }
+
+    /// `SUM(column)`, null when there are no records
+    pub fn sum<C: AsRef<str>>(column: C) -> Aggregate {
+        Aggregate::of(Function::Sum, column)
+    }
+
+    /// `AVG(column)`, null when there are no records
+    pub fn avg<C: AsRef<str>>(column: C) -> Aggregate {
+        Aggregate::of(Function::Avg, column)
+    }
+
+    /// `MIN(column)`, null when there are no records
+    pub fn min<C: AsRef<str>>(column: C) -> Aggregate {
+        Aggregate::of(Function::Min, column)
+    }
+
+    /// `MAX(column)`, null when there are no records
+    pub fn max<C: AsRef<str>>(column: C) -> Aggregate {
+        Aggregate::of(Function::Max, column)
+    }
+
+    pub(crate) fn to_sql(&self) -> String {
+        let function = match self.function {
+            Function::Count => "COUNT",
+            Function::Sum => "SUM",
+            Function::Avg => "AVG",
+            Function::Min => "MIN",
+            Function::Max => "MAX",
+        };
+        format!("{}({})", function, self.column.as_deref().unwrap_or("*"))
+    }
+
+    pub(crate) fn column(&self) -> Option<&str> {
+        self.column.as_deref()
+    }
+
+    fn compare<V: ToValue>(self, op: CompareOp, value: V) -> Having {
+        Having {
+            columns: self.column.iter().cloned().collect(),
+            condition: Condition::compare(&self.to_sql(), op, value),
+        }
+    }
+
+    /// `aggregate = value`
+    pub fn eq<V: ToValue>(self, value: V) -> Having {
+        self.compare(CompareOp::Eq, value)
+    }
+
+    /// `aggregate != value`
+    pub fn ne<V: ToValue>(self, value: V) -> Having {
+        self.compare(CompareOp::Ne, value)
+    }
+
+    /// `aggregate < value`
+    pub fn lt<V: ToValue>(self, value: V) -> Having {
+        self.compare(CompareOp::Lt, value)
+    }
+
+    /// `aggregate <= value`
+    pub fn le<V: ToValue>(self, value: V) -> Having {
+        self.compare(CompareOp::Le, value)
+    }
+
+    /// `aggregate > value`
+    pub fn gt<V: ToValue>(self, value: V) -> Having {
+        self.compare(CompareOp::Gt, value)
+    }
+
+    /// `aggregate >= value`
+    pub fn ge<V: ToValue>(self, value: V) -> Having {
+        self.compare(CompareOp::Ge, value)
+    }
+}
+
+///
+/// A condition on the aggregates of a group, used to filter a `Grouped` query.<br>
+/// Built by comparing an `Aggregate` with a value, and combined with `and`, `or` and `!`.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Having {
+    pub(crate) condition: Condition,
+    /// the table columns the aggregates refer to
+    pub(crate) columns: Vec<String>,
+}
+
+impl Having {
+    ///
+    /// Both this condition and `other`
+    ///
+    pub fn and(mut self, other: Having) -> Having {
+        self.columns.extend(other.columns);
+        Having {
+            condition: self.condition.and(other.condition),
+            columns: self.columns,
+        }
+    }
+
+    ///
+    /// Either this condition or `other`
+    ///
+    pub fn or(mut self, other: Having) -> Having {
+        self.columns.extend(other.columns);
+        Having {
+            condition: self.condition.or(other.condition),
+            columns: self.columns,
+        }
+    }
+}
+
+impl std::ops::Not for Having {
+    type Output = Having;
+
+    fn not(self) -> Having {
+        Having {
+            condition: !self.condition,
+            columns: self.columns,
+        }
+    }
+}
+
+///
+/// A `Query` grouped by one column of model `T` holding values of type `K`, created with
+/// `Query::group_by`.<br>
+/// Each aggregate method returns one `(key, aggregate)` pair per group. The query's filters
+/// select the records before grouping, and its ordering, limit and offset apply to the groups.
+///
+pub struct Grouped<'db, T, K> {
+    db: &'db DB,
+    select: Select,
+    phantom: PhantomData<fn() -> (T, K)>,
+}
+
+impl<'db, T, K> Grouped<'db, T, K>
+where
+    K: FromValue,
+{
+    pub(crate) fn new(db: &'db DB, mut select: Select, column: &str) -> Self {
+        select.columns = vec![column.to_string()];
+        select.group_by = vec![column.to_string()];
+        Grouped {
+            db,
+            select,
+            phantom: PhantomData,
+        }
+    }
+
+    ///
+    /// Only keep groups matching `condition`, multiple conditions must all match
+    ///
+    /// # Arguments
+    /// * `condition` - an `Aggregate` compared with a value, like `Aggregate::count().gt(5)`
+    ///
+    pub fn having(mut self, condition: Having) -> Self {
+        self.select.having.push(condition);
+        self
+    }
+
+    ///
+    /// The number of records in each group
+    ///
+    pub fn count(self) -> Result<Vec<(K, i64)>> {
+        self.aggregate(Aggregate::count())
+    }
+
+    ///
+    /// The sum of `column` in each group
+    ///
+    pub fn sum<V: FromValue>(self, column: Column<T, V>) -> Result<Vec<(K, Option<V>)>> {
+        self.aggregate(Aggregate::sum(column))
+    }
+
+    ///
+    /// The average of `column` in each group
+    ///
+    pub fn avg<V>(self, column: Column<T, V>) -> Result<Vec<(K, Option<f64>)>> {
+        self.aggregate(Aggregate::avg(column))
+    }
+
+    ///
+    /// The smallest value of `column` in each group
+    ///
+    pub fn min<V: FromValue>(self, column: Column<T, V>) -> Result<Vec<(K, Option<V>)>> {
+        self.aggregate(Aggregate::min(column))
+    }
+
+    ///
+    /// The largest value of `column` in each group
+    ///
+    pub fn max<V: FromValue>(self, column: Column<T, V>) -> Result<Vec<(K, Option<V>)>> {
+        self.aggregate(Aggregate::max(column))
+    }
+
+    ///
+    /// Compute `aggregate` for each group, read as `R`
+    ///
+    pub fn aggregate<R: FromValue>(mut self, aggregate: Aggregate) -> Result<Vec<(K, R)>> {
+        self.select.aggregates = vec![aggregate];
+        self.db
+            .select::<Pair<K, R>>(&self.select)?
+            .map(|pair| pair.map(|Pair(key, value)| (key, value)))
+            .collect()
+    }
+}
+
+/// computes a single aggregate over every record `select` matches
+pub(crate) fn scalar<R: FromValue>(db: &DB, mut select: Select, aggregate: Aggregate) -> Result<R> {
+    // a single aggregate row has nothing to order or page through
+    select.columns.clear();
+    select.order.clear();
+    select.limit = None;
+    select.offset = None;
+    select.aggregates = vec![aggregate];
+    match db.select::<Scalar<R>>(&select)?.next() {
+        Some(scalar) => scalar.map(|Scalar(value)| value),
+        None => Err(Error::Decode(String::from("aggregate returned no rows"))),
+    }
+}
+
+/// an aggregate row, read by position since its column is named after the sql expression
+struct Scalar<R>(R);
+
+impl<R: FromValue> FromRow for Scalar<R> {
+    fn from_row(row: &RowRef) -> Result<Self> {
+        Ok(Scalar(row.read(0)?))
+    }
+}
+
+/// a group's key and aggregate
+struct Pair<K, R>(K, R);
+
+impl<K: FromValue, R: FromValue> FromRow for Pair<K, R> {
+    fn from_row(row: &RowRef) -> Result<Self> {
+        Ok(Pair(row.read(0)?, row.read(1)?))
+    }
+}
@@ -0,0 +1,560 @@
+use crate::config::DbConfig;
+use crate::error::{Error, Result};
+use crate::row::{FromRow, ToRow};
+use crate::transaction::Transaction;
+use crate::{RowIter, DB};
+use futures::channel::{mpsc, oneshot};
+use futures::Stream;
+use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc as std_mpsc;
+use std::task::{Context, Poll};
+use std::thread;
+use std::vec;
+
+/// the number of rows a `RowStream` asks the worker for at a time
+const STREAM_BATCH: usize = 64;
+
+static NEXT_STREAM: AtomicU64 = AtomicU64::new(0);
+
+type Job = Box<dyn FnOnce(&DB) + Send>;
+
+/// an open select on the worker, reads the next batch and returns whether there may be more
+type Cursor<'db> = Box<dyn FnMut() -> bool + 'db>;
+
+type OpenCursor = Box<dyn for<'db> FnOnce(&'db DB) -> Option<Cursor<'db>> + Send>;
+
+enum Task {
+    Run(Job),
+    Open(u64, OpenCursor),
+    Next(u64),
+    Close(u64),
+}
+
+///
+/// An async handle to a `DB`, available with the `async` feature.<br>
+/// The `DB` lives on a dedicated worker thread, so awaiting an operation never blocks the executor.
+/// Operations run one at a time in the order they were called.<br>
+/// Cloning the handle is cheap and every clone uses the same worker,
+/// which stops once all clones are dropped.
+///
+#[derive(Clone)]
+pub struct AsyncDb {
+    tasks: std_mpsc::Sender<Task>,
+}
+
+impl AsyncDb {
+    ///
+    /// Open a DB on a new worker thread
+    ///
+    /// # Arguments
+    /// * `dsn` - the database to open, in any form accepted by `DB::open`
+    ///
+    pub async fn open<P: AsRef<Path>>(dsn: P) -> Result<AsyncDb> {
+        AsyncDb::open_with(dsn, DbConfig::default()).await
+    }
+
+    ///
+    /// Open a DB with connection settings on a new worker thread, see `DB::open_with`
+    ///
+    /// # Arguments
+    /// * `dsn` - the database to open, in any form accepted by `DB::open`
+    /// * `config` - the settings to apply
+    ///
+    pub async fn open_with<P: AsRef<Path>>(dsn: P, config: DbConfig) -> Result<AsyncDb> {
+        let dsn = dsn.as_ref().to_path_buf();
+        let (opened, result) = oneshot::channel();
+        let (tasks, queue) = std_mpsc::channel();
+        thread::Builder::new()
+            .name(String::from("rsorm-async"))
+            .spawn(move || match DB::open_with(dsn, &config) {
+                Ok(db) => {
+                    let _ = opened.send(Ok(()));
+                    work(db, queue);
+                }
+                Err(e) => {
+                    let _ = opened.send(Err(e));
+                }
+            })
+            .map_err(|e| Error::Sqlite {
+                code: None,
+                message: format!("could not spawn the async worker: {}", e),
+            })?;
+        result.await.map_err(|_| Error::WorkerStopped)??;
+        Ok(AsyncDb { tasks })
+    }
+
+    ///
+    /// Move an open `DB` onto a new worker thread
+    ///
+    pub fn from_db(db: DB) -> AsyncDb {
+        let (tasks, queue) = std_mpsc::channel();
+        thread::spawn(move || work(db, queue));
+        AsyncDb { tasks }
+    }
+
+    ///
+    /// Run `f` with the `DB` on the worker thread.<br>
+    /// The building block for the other methods, and for anything they don't cover.
+    ///
+    /// # Arguments
+    /// * `f` - the blocking work to run
+    ///
+    pub async fn run<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&DB) -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let (sender, result) = oneshot::channel();
+        let job: Job = Box::new(move |db| {
+            let _ = sender.send(f(db));
+        });
+        self.tasks
+            .send(Task::Run(job))
+            .map_err(|_| Error::WorkerStopped)?;
+        result.await.map_err(|_| Error::WorkerStopped)?
+    }
+
+    ///
+    /// See `DB::create_table`
+    ///
+    pub async fn create_table(&self, schema: (String, Vec<(String, String)>)) -> Result<()> {
+        self.run(move |db| db.create_table(schema)).await
+    }
+
+    ///
+    /// See `DB::insert`
+    ///
+    pub async fn insert(&self, table: &str, data: (Vec<String>, Vec<String>)) -> Result<()> {
+        let table = table.to_string();
+        self.run(move |db| db.insert(&table, data)).await
+    }
+
+    ///
+    /// See `DB::insert_model`
+    ///
+    pub async fn insert_model<T>(&self, table: &str, model: T) -> Result<()>
+    where
+        T: ToRow + Send + 'static,
+    {
+        let table = table.to_string();
+        self.run(move |db| db.insert_model(&table, &model)).await
+    }
+
+    ///
+    /// See `DB::insert_many`
+    ///
+    pub async fn insert_many<T>(&self, table: &str, models: Vec<T>) -> Result<usize>
+    where
+        T: ToRow + Send + 'static,
+    {
+        let table = table.to_string();
+        self.run(move |db| db.insert_many(&table, &models)).await
+    }
+
+    ///
+    /// See `DB::insert_values`
+    ///
+    pub async fn insert_values(
+        &self,
+        table: &str,
+        rows: Vec<(Vec<String>, Vec<String>)>,
+    ) -> Result<usize> {
+        let table = table.to_string();
+        self.run(move |db| db.insert_values(&table, rows)).await
+    }
+
+    ///
+    /// See `DB::select_where`
+    ///
+    pub async fn select_where<T>(
+        &self,
+        table: &str,
+        data: (Vec<String>, Vec<String>),
+    ) -> Result<Vec<T>>
+    where
+        T: FromRow + Send + 'static,
+    {
+        let table = table.to_string();
+        self.run(move |db| db.select_where(&table, data)).await
+    }
+
+    ///
+    /// See `DB::select_all`
+    ///
+    pub async fn select_all<T>(&self, table: &str) -> Result<Vec<T>>
+    where
+        T: FromRow + Send + 'static,
+    {
+        let table = table.to_string();
+        self.run(move |db| db.select_all(&table)).await
+    }
+
+    ///
+    /// Stream the records that match the conditions, see `DB::iter_where`.<br>
+    /// The select stays open on the worker and rows are read in small batches
+    /// as the stream's consumer asks for them.
+    /// Other operations run between batches, so a slow consumer never stalls the worker.
+    ///
+    pub async fn iter_where<T>(
+        &self,
+        table: &str,
+        data: (Vec<String>, Vec<String>),
+    ) -> Result<RowStream<T>>
+    where
+        T: FromRow + Send + 'static,
+    {
+        let table = table.to_string();
+        self.stream(move |db| db.iter_where(&table, data)).await
+    }
+
+    ///
+    /// Stream all records from the table, see `DB::iter_all` and `AsyncDb::iter_where`
+    ///
+    pub async fn iter_all<T>(&self, table: &str) -> Result<RowStream<T>>
+    where
+        T: FromRow + Send + 'static,
+    {
+        let table = table.to_string();
+        self.stream(move |db| db.iter_all(&table)).await
+    }
+
+    ///
+    /// See `DB::delete`
+    ///
+    pub async fn delete(&self, table: &str, data: (Vec<String>, Vec<String>)) -> Result<()> {
+        let table = table.to_string();
+        self.run(move |db| db.delete(&table, data)).await
+    }
+
+    ///
+    /// See `DB::delete_all`
+    ///
+    pub async fn delete_all(&self, table: &str) -> Result<()> {
+        let table = table.to_string();
+        self.run(move |db| db.delete_all(&table)).await
+    }
+
+    ///
+    /// See `DB::update`
+    ///
+    pub async fn update(
+        &self,
+        table: &str,
+        data: (Vec<String>, Vec<String>),
+        conditions: (Vec<String>, Vec<String>),
+    ) -> Result<()> {
+        let table = table.to_string();
+        self.run(move |db| db.update(&table, data, conditions))
+            .await
+    }
+
+    ///
+    /// Run `f` inside a transaction on the worker thread, see `DB::transaction`.<br>
+    /// No other operation runs on the worker until the transaction finishes.
+    ///
+    /// # Arguments
+    /// * `f` - a closure that receives the transaction handle
+    ///
+    pub async fn transaction<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&Transaction) -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        self.run(move |db| db.transaction(f)).await
+    }
+
+    async fn stream<F, T>(&self, open: F) -> Result<RowStream<T>>
+    where
+        F: FnOnce(&DB) -> Result<RowIter<'_, T>> + Send + 'static,
+        T: FromRow + Send + 'static,
+    {
+        let id = NEXT_STREAM.fetch_add(1, Ordering::Relaxed);
+        let (opened, result) = oneshot::channel();
+        let (batches, receiver) = mpsc::channel(1);
+        let open: OpenCursor = Box::new(move |db| match open(db) {
+            Ok(iter) => {
+                let _ = opened.send(Ok(()));
+                Some(cursor(iter, batches))
+            }
+            Err(e) => {
+                let _ = opened.send(Err(e));
+                None
+            }
+        });
+        self.tasks
+            .send(Task::Open(id, open))
+            .map_err(|_| Error::WorkerStopped)?;
+        result.await.map_err(|_| Error::WorkerStopped)??;
+        Ok(RowStream {
+            id,
+            tasks: self.tasks.clone(),
+            batches: receiver,
+            batch: Vec::new().into_iter(),
+            requested: false,
+        })
+    }
+}
+
+fn cursor<'db, T>(
+    mut iter: RowIter<'db, T>,
+    mut batches: mpsc::Sender<Vec<Result<T>>>,
+) -> Cursor<'db>
+where
+    T: FromRow + 'db,
+{
+    Box::new(move || {
+        let batch: Vec<Result<T>> = iter.by_ref().take(STREAM_BATCH).collect();
+        if batch.is_empty() {
+            return false;
+        }
+        let full = batch.len() == STREAM_BATCH;
+        // the stream only asks for a batch once it has taken the last one, so there is always room
+        batches.try_send(batch).is_ok() && full
+    })
+}
+
+fn work(db: DB, queue: std_mpsc::Receiver<Task>) {
+    // declared after `db` so the open selects are dropped before it
+    let mut cursors: HashMap<u64, Cursor> = HashMap::new();
+    for task in queue {
+        // a panicking task drops its result sender, which its caller sees as `WorkerStopped`
+        let _ = catch_unwind(AssertUnwindSafe(|| match task {
+            Task::Run(job) => job(&db),
+            Task::Open(id, open) => {
+                if let Some(cursor) = open(&db) {
+                    cursors.insert(id, cursor);
+                }
+            }
+            Task::Next(id) => {
+                // taken out while it reads, so a panicking cursor is dropped and its stream ends
+                if let Some(mut cursor) = cursors.remove(&id) {
+                    if cursor() {
+                        cursors.insert(id, cursor);
+                    }
+                }
+            }
+            Task::Close(id) => {
+                cursors.remove(&id);
+            }
+        }));
+    }
+}
+
+///
+/// An async stream of the records returned by a select, see `AsyncDb::iter_where`.<br>
+/// Dropping the stream closes the select on the worker.
+///
+pub struct RowStream<T> {
+    id: u64,
+    tasks: std_mpsc::Sender<Task>,
+    batches: mpsc::Receiver<Vec<Result<T>>>,
+    batch: vec::IntoIter<Result<T>>,
+    requested: bool,
+}
+
+// never pinned structurally, the fields are only moved through `&mut`
+impl<T> Unpin for RowStream<T> {}
+
+impl<T> Stream for RowStream<T> {
+    type Item = Result<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(row) = self.batch.next() {
+                return Poll::Ready(Some(row));
+            }
+            if !self.requested {
+                // if the worker is gone so is the cursor, and `batches` reports the end
+                let _ = self.tasks.send(Task::Next(self.id));
+                self.requested = true;
+            }
+            match Pin::new(&mut self.batches).poll_next(cx) {
+                Poll::Ready(Some(batch)) => {
+                    self.batch = batch.into_iter();
+                    self.requested = false;
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<T> Drop for RowStream<T> {
+    fn drop(&mut self) {
+        let _ = self.tasks.send(Task::Close(self.id));
+    }
+}
+
+#[cfg(test)]
+#[allow(non_camel_case_types, dead_code)]
+mod tests {
+    use super::*;
+    use crate::sql;
+    use futures::executor::LocalPool;
+    use futures::task::LocalSpawnExt;
+    use futures::StreamExt;
+    use migrate_table::MigrateTable;
+    use migrate_table_derive::MigrateTable;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(MigrateTable, Deserialize, Serialize, Debug, PartialEq)]
+    struct testModel {
+        city: String,
+        population: u64,
+        avg_age: f64,
+    }
+
+    fn model(city: &str, population: u64) -> testModel {
+        testModel {
+            city: city.to_string(),
+            population,
+            avg_age: 40.5,
+        }
+    }
+
+    async fn setup() -> AsyncDb {
+        let db = AsyncDb::open(":memory:").await.unwrap();
+        db.create_table(testModel::generate_schema()).await.unwrap();
+        db
+    }
+
+    #[test]
+    fn test_mirrors_db() {
+        futures::executor::block_on(async {
+            let db = setup().await;
+            db.insert(
+                "testModel",
+                sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+            )
+            .await
+            .unwrap();
+            db.insert_model("testModel", model("Sandy", 10000))
+                .await
+                .unwrap();
+            db.update("testModel", sql!(population = 10001), sql!(city = "Sandy"))
+                .await
+                .unwrap();
+
+            let out: Vec<testModel> = db
+                .select_where("testModel", sql!(city = "Sandy"))
+                .await
+                .unwrap();
+            assert_eq!(out, vec![model("Sandy", 10001)]);
+
+            db.delete("testModel", sql!(city = "Sandy")).await.unwrap();
+            let out: Vec<testModel> = db.select_all("testModel").await.unwrap();
+            assert_eq!(out.len(), 1);
+
+            let result = db.select_all::<testModel>("idontexist").await;
+            assert!(matches!(result, Err(Error::UnknownTable(_))));
+        });
+    }
+
+    #[test]
+    fn test_transaction() {
+        futures::executor::block_on(async {
+            let db = setup().await;
+            let result = db
+                .transaction(|tx| {
+                    tx.insert_model("testModel", &model("Gresham", 1))?;
+                    tx.insert("idontexist", sql!(city = "Sandy"))
+                })
+                .await;
+            assert!(matches!(result, Err(Error::UnknownTable(_))));
+            assert!(db
+                .select_all::<testModel>("testModel")
+                .await
+                .unwrap()
+                .is_empty());
+
+            db.transaction(|tx| tx.insert_model("testModel", &model("Gresham", 1)))
+                .await
+                .unwrap();
+            assert_eq!(
+                1,
+                db.select_all::<testModel>("testModel").await.unwrap().len()
+            );
+        });
+    }
+
+    #[test]
+    fn test_streaming_select() {
+        let mut pool = LocalPool::new();
+        let spawner = pool.spawner();
+        pool.run_until(async move {
+            let db = setup().await;
+            let models: Vec<testModel> = (0..200).map(|i| model("City", i)).collect();
+            assert_eq!(200, db.insert_many("testModel", models).await.unwrap());
+
+            // an operation queued while the stream is open runs between its batches
+            let writer = db.clone();
+            let handle = spawner
+                .spawn_local_with_handle(async move {
+                    writer.insert_model("testModel", model("Sandy", 1)).await
+                })
+                .unwrap();
+
+            let rows: Vec<testModel> = db
+                .iter_where("testModel", sql!(city = "City"))
+                .await
+                .unwrap()
+                .map(|row| row.unwrap())
+                .collect()
+                .await;
+            assert_eq!(rows.len(), 200);
+            assert_eq!(rows[199].population, 199);
+            handle.await.unwrap();
+
+            // dropping a stream early frees the worker for the next operation
+            let mut stream = db.iter_all::<testModel>("testModel").await.unwrap();
+            assert!(stream.next().await.unwrap().is_ok());
+            drop(stream);
+            assert_eq!(
+                201,
+                db.select_all::<testModel>("testModel").await.unwrap().len()
+            );
+
+            let missing = db.iter_all::<testModel>("idontexist").await;
+            assert!(matches!(missing, Err(Error::UnknownTable(_))));
+        });
+    }
+
+    #[test]
+    fn test_operations_while_streaming() {
+        futures::executor::block_on(async {
+            let db = setup().await;
+            let models: Vec<testModel> = (0..200).map(|i| model("City", i)).collect();
+            db.insert_many("testModel", models).await.unwrap();
+
+            // a stream that isn't being read doesn't hold up other operations
+            let mut stream = db.iter_all::<testModel>("testModel").await.unwrap();
+            assert_eq!(stream.next().await.unwrap().unwrap().population, 0);
+            assert_eq!(
+                200,
+                db.select_all::<testModel>("testModel").await.unwrap().len()
+            );
+            db.insert_model("testModel", model("Sandy", 1))
+                .await
+                .unwrap();
+
+            let rest: Vec<testModel> = stream.map(|row| row.unwrap()).collect().await;
+            assert!(rest.len() >= 199);
+            assert_eq!(rest[198].population, 199);
+        });
+    }
+
+    #[test]
+    fn test_worker_survives_panics() {
+        futures::executor::block_on(async {
+            let db = setup().await;
+            let result = db.run(|_| -> Result<()> { panic!("job failed") }).await;
+            assert!(matches!(result, Err(Error::WorkerStopped)));
+            assert!(db.select_all::<testModel>("testModel").await.is_ok());
+        });
+    }
+}
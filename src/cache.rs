@@ -0,0 +1,218 @@
+use std::cell::{Cell, RefCell};
+use std::ops::{Deref, DerefMut};
+
+/// the number of statements a `DB` keeps prepared unless configured otherwise
+pub(crate) const DEFAULT_CAPACITY: usize = 32;
+
+///
+/// A least-recently-used cache of prepared statements, keyed by their sql text.<br>
+/// Statements are taken out of the cache while in use and returned, reset, when dropped.
+///
+pub(crate) struct StatementCache {
+    capacity: Cell<usize>,
+    // least recently used first
+    entries: RefCell<Vec<(String, sqlite::Statement<'static>)>>,
+}
+
+///
+/// An sqlite connection together with the cache of the statements prepared on it.<br>
+/// Owning both is what keeps the cache sound: cached statements can only come from this
+/// connection, and they are finalized before it closes.
+///
+pub(crate) struct CachingConnection {
+    statements: StatementCache,
+    conn: sqlite::Connection,
+}
+
+// SAFETY: an sqlite connection may be used from any one thread at a time, which is why
+// `sqlite::Connection` is `Send`. The cached statements are only reachable through this type,
+// so they move between threads together with their connection and are never used apart from it.
+unsafe impl Send for CachingConnection {}
+
+impl CachingConnection {
+    pub(crate) fn new(conn: sqlite::Connection, capacity: usize) -> Self {
+        CachingConnection {
+            statements: StatementCache::new(capacity),
+            conn,
+        }
+    }
+
+    ///
+    /// Take the statement for `sql` out of the cache, or prepare it on a miss.<br>
+    /// The statement is returned to the cache, reset, when dropped.
+    ///
+    pub(crate) fn prepare_cached(&self, sql: &str) -> sqlite::Result<CachedStatement<'_>> {
+        self.statements.prepare(&self.conn, sql)
+    }
+
+    pub(crate) fn statements(&self) -> &StatementCache {
+        &self.statements
+    }
+}
+
+impl Deref for CachingConnection {
+    type Target = sqlite::Connection;
+
+    fn deref(&self) -> &sqlite::Connection {
+        &self.conn
+    }
+}
+
+impl Drop for CachingConnection {
+    fn drop(&mut self) {
+        // finalize the cached statements while the connection they were prepared on is open,
+        // the fields are dropped only after this
+        self.statements.entries.get_mut().clear();
+    }
+}
+
+impl StatementCache {
+    fn new(capacity: usize) -> Self {
+        StatementCache {
+            capacity: Cell::new(capacity),
+            entries: RefCell::new(Vec::new()),
+        }
+    }
+
+    // `conn` must be the connection this cache belongs to, see `CachingConnection`
+    fn prepare<'c>(
+        &'c self,
+        conn: &'c sqlite::Connection,
+        sql: &str,
+    ) -> sqlite::Result<CachedStatement<'c>> {
+        let cached = {
+            let mut entries = self.entries.borrow_mut();
+            let hit = entries.iter().position(|(key, _)| key == sql);
+            hit.map(|i| entries.remove(i).1)
+        };
+        let stmt = match cached {
+            Some(stmt) => stmt,
+            None => conn.prepare(sql)?,
+        };
+        Ok(CachedStatement {
+            cache: self,
+            sql: sql.to_string(),
+            stmt: Some(stmt),
+        })
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity.get()
+    }
+
+    pub(crate) fn set_capacity(&self, capacity: usize) {
+        self.capacity.set(capacity);
+        let mut entries = self.entries.borrow_mut();
+        let excess = entries.len().saturating_sub(capacity);
+        entries.drain(..excess);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    fn put(&self, sql: String, stmt: sqlite::Statement<'_>) {
+        let mut entries = self.entries.borrow_mut();
+        // the same sql can be checked out twice at once, only one copy is kept
+        if self.capacity.get() == 0 || entries.iter().any(|(key, _)| *key == sql) {
+            return;
+        }
+        // SAFETY: only the `'c` borrow of the connection is erased. The statement was prepared
+        // on the connection of the `CachingConnection` owning this cache, whose `Drop` clears
+        // the cache before that connection closes, so the statement never outlives it.
+        let stmt = unsafe {
+            std::mem::transmute::<sqlite::Statement<'_>, sqlite::Statement<'static>>(stmt)
+        };
+        entries.push((sql, stmt));
+        if entries.len() > self.capacity.get() {
+            entries.remove(0);
+        }
+    }
+}
+
+///
+/// A statement checked out of a `StatementCache`, returned to it when dropped.
+///
+pub(crate) struct CachedStatement<'c> {
+    cache: &'c StatementCache,
+    sql: String,
+    stmt: Option<sqlite::Statement<'c>>,
+}
+
+impl<'c> Deref for CachedStatement<'c> {
+    type Target = sqlite::Statement<'c>;
+
+    fn deref(&self) -> &Self::Target {
+        self.stmt
+            .as_ref()
+            .expect("statement is present until dropped")
+    }
+}
+
+impl<'c> DerefMut for CachedStatement<'c> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.stmt
+            .as_mut()
+            .expect("statement is present until dropped")
+    }
+}
+
+impl<'c> Drop for CachedStatement<'c> {
+    fn drop(&mut self) {
+        if let Some(mut stmt) = self.stmt.take() {
+            // a statement that can't be reset is finalized rather than reused
+            if stmt.reset().is_ok() {
+                self.cache.put(std::mem::take(&mut self.sql), stmt);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuse_and_eviction() {
+        let conn = sqlite::open(":memory:").unwrap();
+        let cache = StatementCache::new(2);
+
+        for sql in ["SELECT 1", "SELECT 2", "SELECT 1", "SELECT 3"].iter() {
+            let mut stmt = cache.prepare(&conn, sql).unwrap();
+            assert_eq!(stmt.next().unwrap(), sqlite::State::Row);
+        }
+        // "SELECT 2" was least recently used when "SELECT 3" was added
+        let keys: Vec<String> = cache.entries.borrow().iter().map(|e| e.0.clone()).collect();
+        assert_eq!(keys, vec!["SELECT 1", "SELECT 3"]);
+
+        cache.set_capacity(1);
+        assert_eq!(cache.len(), 1);
+        cache.set_capacity(0);
+        drop(cache.prepare(&conn, "SELECT 1").unwrap());
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_returned_statements_are_reset() {
+        let conn = sqlite::open(":memory:").unwrap();
+        let cache = StatementCache::new(4);
+
+        let mut stmt = cache.prepare(&conn, "SELECT 1 UNION ALL SELECT 2").unwrap();
+        stmt.next().unwrap();
+        drop(stmt);
+
+        let mut stmt = cache.prepare(&conn, "SELECT 1 UNION ALL SELECT 2").unwrap();
+        assert_eq!(cache.len(), 0);
+        stmt.next().unwrap();
+        assert_eq!(stmt.read::<i64>(0).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_prepare_error() {
+        let conn = sqlite::open(":memory:").unwrap();
+        let cache = StatementCache::new(4);
+        assert!(cache.prepare(&conn, "SELECT * FROM missing").is_err());
+        assert_eq!(cache.len(), 0);
+    }
+}
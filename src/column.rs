@@ -0,0 +1,228 @@
+use crate::query::Condition;
+use crate::value::ToValue;
+use std::fmt;
+use std::marker::PhantomData;
+
+///
+/// A typed column of model `M` holding values of type `V`.<br>
+/// `#[derive(MigrateTable)]` generates one constant per field, named after the field in
+/// upper case, such as `Person::AGE`. Conditions built from them are checked at compile time:
+/// a misspelled column doesn't exist and a value of the wrong type doesn't convert.
+///
+/// ```
+/// use migrate_table::MigrateTable;
+/// use migrate_table_derive::MigrateTable;
+///
+/// #[derive(MigrateTable)]
+/// struct Person {
+///     name: String,
+///     age: u64,
+/// }
+///
+/// let adults = Person::AGE.ge(18).and(Person::NAME.like("J%"));
+/// ```
+///
+/// A column that isn't a field of the model doesn't compile:
+///
+/// ```compile_fail,E0599
+/// # use migrate_table::MigrateTable;
+/// # use migrate_table_derive::MigrateTable;
+/// # #[derive(MigrateTable)]
+/// # struct Person {
+/// #     name: String,
+/// #     age: u64,
+/// # }
+/// let adults = Person::AEG.ge(18);
+/// ```
+///
+/// Nor does a value of the wrong type:
+///
+/// ```compile_fail,E0277
+/// # use migrate_table::MigrateTable;
+/// # use migrate_table_derive::MigrateTable;
+/// # #[derive(MigrateTable)]
+/// # struct Person {
+/// #     name: String,
+/// #     age: u64,
+/// # }
+/// let adults = Person::AGE.eq("x");
+/// ```
+///
+pub struct Column<M, V> {
+    name: &'static str,
+    phantom: PhantomData<fn() -> (M, V)>,
+}
+
+impl<M, V> Column<M, V> {
+    pub const fn new(name: &'static str) -> Self {
+        Column {
+            name,
+            phantom: PhantomData,
+        }
+    }
+
+    ///
+    /// The column's name in the table
+    ///
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// `column IS NULL`
+    pub fn is_null(self) -> Condition {
+        Condition::is_null(self.name)
+    }
+
+    /// `column IS NOT NULL`
+    pub fn is_not_null(self) -> Condition {
+        Condition::is_not_null(self.name)
+    }
+}
+
+///
+/// A value that can be compared with a column holding `V`.<br>
+/// Implemented for `V` itself, for `T` where the column holds `Option<T>`, and for `&str`
+/// on text columns, so literals like `18` or `"J%"` take the column's type.
+///
+pub trait Operand<V> {
+    fn into_operand(self) -> V;
+}
+
+impl<V> Operand<V> for V {
+    fn into_operand(self) -> V {
+        self
+    }
+}
+
+impl<T> Operand<Option<T>> for T {
+    fn into_operand(self) -> Option<T> {
+        Some(self)
+    }
+}
+
+impl Operand<String> for &str {
+    fn into_operand(self) -> String {
+        self.to_string()
+    }
+}
+
+impl Operand<Option<String>> for &str {
+    fn into_operand(self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+impl<M, V: ToValue> Column<M, V> {
+    /// `column = value`, or `column IS NULL` for `None`
+    pub fn eq<X: Operand<V>>(self, value: X) -> Condition {
+        Condition::eq(self.name, value.into_operand())
+    }
+
+    /// `column != value`, or `column IS NOT NULL` for `None`
+    pub fn ne<X: Operand<V>>(self, value: X) -> Condition {
+        Condition::ne(self.name, value.into_operand())
+    }
+
+    /// `column < value`
+    pub fn lt<X: Operand<V>>(self, value: X) -> Condition {
+        Condition::lt(self.name, value.into_operand())
+    }
+
+    /// `column <= value`
+    pub fn le<X: Operand<V>>(self, value: X) -> Condition {
+        Condition::le(self.name, value.into_operand())
+    }
+
+    /// `column > value`
+    pub fn gt<X: Operand<V>>(self, value: X) -> Condition {
+        Condition::gt(self.name, value.into_operand())
+    }
+
+    /// `column >= value`
+    pub fn ge<X: Operand<V>>(self, value: X) -> Condition {
+        Condition::ge(self.name, value.into_operand())
+    }
+
+    /// `column IN (values...)`, never true for an empty list
+    pub fn in_<X, I>(self, values: I) -> Condition
+    where
+        X: Operand<V>,
+        I: IntoIterator<Item = X>,
+    {
+        Condition::in_(self.name, values.into_iter().map(Operand::into_operand))
+    }
+}
+
+impl<M> Column<M, String> {
+    /// `column LIKE pattern`, only available on text columns
+    pub fn like(self, pattern: &str) -> Condition {
+        Condition::like(self.name, pattern)
+    }
+}
+
+impl<M> Column<M, Option<String>> {
+    /// `column LIKE pattern`, only available on text columns
+    pub fn like(self, pattern: &str) -> Condition {
+        Condition::like(self.name, pattern)
+    }
+}
+
+// implemented by hand, deriving would require `M` and `V` to implement them too
+impl<M, V> Clone for Column<M, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<M, V> Copy for Column<M, V> {}
+
+impl<M, V> fmt::Debug for Column<M, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Column({})", self.name)
+    }
+}
+
+impl<M, V> AsRef<str> for Column<M, V> {
+    fn as_ref(&self) -> &str {
+        self.name
+    }
+}
+
+///
+/// Columns of model `M` to select, read into `Output`.<br>
+/// A single column reads its value, and a tuple of columns reads a tuple of their values,
+/// so `(Person::NAME, Person::AGE)` selects `(String, u64)`.
+///
+pub trait Projection<M> {
+    type Output;
+
+    fn columns(&self) -> Vec<String>;
+}
+
+impl<M, V> Projection<M> for Column<M, V> {
+    type Output = V;
+
+    fn columns(&self) -> Vec<String> {
+        vec![self.name.to_string()]
+    }
+}
+
+macro_rules! tuple_projection {
+    ($($v:ident $i:tt),+) => {
+        impl<M, $($v),+> Projection<M> for ($(Column<M, $v>,)+) {
+            type Output = ($($v,)+);
+
+            fn columns(&self) -> Vec<String> {
+                vec![$(self.$i.name.to_string()),+]
+            }
+        }
+    };
+}
+
+tuple_projection!(A 0, B 1);
+tuple_projection!(A 0, B 1, C 2);
+tuple_projection!(A 0, B 1, C 2, D 3);
+tuple_projection!(A 0, B 1, C 2, D 3, E 4);
+tuple_projection!(A 0, B 1, C 2, D 3, E 4, F 5);
+tuple_projection!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+tuple_projection!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
@@ -0,0 +1,270 @@
+use crate::dsn::{Dsn, Mode};
+use crate::error::{Error, Result};
+use crate::retry::RetryPolicy;
+use std::time::Duration;
+
+///
+/// sqlite's journal modes, set with `PRAGMA journal_mode`.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    /// write-ahead logging, which lets readers run alongside a writer
+    Wal,
+    Off,
+}
+
+impl JournalMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            JournalMode::Delete => "delete",
+            JournalMode::Truncate => "truncate",
+            JournalMode::Persist => "persist",
+            JournalMode::Memory => "memory",
+            JournalMode::Wal => "wal",
+            JournalMode::Off => "off",
+        }
+    }
+}
+
+///
+/// How often sqlite syncs to disk, set with `PRAGMA synchronous`.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Synchronous {
+    Off,
+    /// safe from corruption in WAL mode, and much faster than `Full`
+    Normal,
+    Full,
+    Extra,
+}
+
+///
+/// Where sqlite keeps temporary tables and indices, set with `PRAGMA temp_store`.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TempStore {
+    Default,
+    File,
+    Memory,
+}
+
+///
+/// Connection settings passed to `DB::open_with`.<br>
+/// Every setting left unset keeps sqlite's default, or the one given in the dsn.
+///
+#[derive(Clone, Debug, Default)]
+pub struct DbConfig {
+    pub(crate) mode: Option<Mode>,
+    pub(crate) journal_mode: Option<JournalMode>,
+    pub(crate) synchronous: Option<Synchronous>,
+    pub(crate) foreign_keys: Option<bool>,
+    pub(crate) busy_timeout: Option<Duration>,
+    pub(crate) cache_size: Option<i64>,
+    pub(crate) temp_store: Option<TempStore>,
+    pub(crate) statement_cache_capacity: Option<usize>,
+    pub(crate) retry: Option<RetryPolicy>,
+}
+
+impl DbConfig {
+    ///
+    /// A config that changes nothing, to be built on with the other methods
+    ///
+    pub fn new() -> Self {
+        DbConfig::default()
+    }
+
+    ///
+    /// Open the database read-only, it must already exist
+    ///
+    pub fn read_only(mut self) -> Self {
+        self.mode = Some(Mode::ReadOnly);
+        self
+    }
+
+    ///
+    /// Open the database read-write, creating it if it doesn't exist
+    ///
+    pub fn read_write_create(mut self) -> Self {
+        self.mode = Some(Mode::ReadWriteCreate);
+        self
+    }
+
+    ///
+    /// Set the journal mode, `JournalMode::Wal` is usually the right choice for services
+    ///
+    pub fn journal_mode(mut self, mode: JournalMode) -> Self {
+        self.journal_mode = Some(mode);
+        self
+    }
+
+    ///
+    /// Set how often sqlite syncs to disk
+    ///
+    pub fn synchronous(mut self, synchronous: Synchronous) -> Self {
+        self.synchronous = Some(synchronous);
+        self
+    }
+
+    ///
+    /// Enforce FOREIGN KEY constraints, which sqlite leaves off by default
+    ///
+    pub fn foreign_keys(mut self, enabled: bool) -> Self {
+        self.foreign_keys = Some(enabled);
+        self
+    }
+
+    ///
+    /// How long to wait for another connection's lock before failing with SQLITE_BUSY
+    ///
+    pub fn busy_timeout(mut self, timeout: Duration) -> Self {
+        self.busy_timeout = Some(timeout);
+        self
+    }
+
+    ///
+    /// Set sqlite's page cache size
+    ///
+    /// # Arguments
+    /// * `size` - a number of pages when positive, or a number of KiB when negative,
+    ///   as with `PRAGMA cache_size`
+    ///
+    pub fn cache_size(mut self, size: i64) -> Self {
+        self.cache_size = Some(size);
+        self
+    }
+
+    ///
+    /// Set where temporary tables and indices are kept
+    ///
+    pub fn temp_store(mut self, store: TempStore) -> Self {
+        self.temp_store = Some(store);
+        self
+    }
+
+    ///
+    /// Set how many prepared statements the DB keeps cached, see `DB::set_statement_cache_capacity`
+    ///
+    pub fn statement_cache_capacity(mut self, capacity: usize) -> Self {
+        self.statement_cache_capacity = Some(capacity);
+        self
+    }
+
+    ///
+    /// Retry writes and transactions that fail because the database is locked,
+    /// see `DB::set_retry_policy`
+    ///
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// overrides the dsn's open mode and busy timeout with the configured ones
+    pub(crate) fn apply_to_dsn(&self, dsn: &mut Dsn) {
+        // a memory database has no file to open read-only or to create
+        if let Some(mode) = self.mode {
+            if dsn.mode != Mode::Memory {
+                dsn.mode = mode;
+            }
+        }
+        if let Some(timeout) = self.busy_timeout {
+            dsn.busy_timeout = Some(timeout.as_millis() as usize);
+        }
+    }
+
+    /// runs the configured pragmas on a newly opened connection
+    pub(crate) fn apply(&self, conn: &sqlite::Connection, memory: bool) -> Result<()> {
+        if let Some(mode) = self.journal_mode {
+            let mut stmt = conn.prepare(format!("PRAGMA journal_mode = {}", mode.as_str()))?;
+            stmt.next()?;
+            let applied = stmt.read::<String>(0)?;
+            // memory databases always report the memory journal, whatever was asked for
+            if !memory && !applied.eq_ignore_ascii_case(mode.as_str()) {
+                return Err(Error::Sqlite {
+                    code: None,
+                    message: format!(
+                        "journal_mode could not be set to {}, it is {}",
+                        mode.as_str(),
+                        applied
+                    ),
+                });
+            }
+        }
+        for pragma in self.pragmas() {
+            conn.execute(pragma)?;
+        }
+        Ok(())
+    }
+
+    fn pragmas(&self) -> Vec<String> {
+        let mut pragmas = Vec::new();
+        if let Some(synchronous) = self.synchronous {
+            let level = match synchronous {
+                Synchronous::Off => "OFF",
+                Synchronous::Normal => "NORMAL",
+                Synchronous::Full => "FULL",
+                Synchronous::Extra => "EXTRA",
+            };
+            pragmas.push(format!("PRAGMA synchronous = {}", level));
+        }
+        if let Some(enabled) = self.foreign_keys {
+            let enabled = if enabled { "ON" } else { "OFF" };
+            pragmas.push(format!("PRAGMA foreign_keys = {}", enabled));
+        }
+        if let Some(size) = self.cache_size {
+            pragmas.push(format!("PRAGMA cache_size = {}", size));
+        }
+        if let Some(store) = self.temp_store {
+            let store = match store {
+                TempStore::Default => "DEFAULT",
+                TempStore::File => "FILE",
+                TempStore::Memory => "MEMORY",
+            };
+            pragmas.push(format!("PRAGMA temp_store = {}", store));
+        }
+        pragmas
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pragmas() {
+        assert!(DbConfig::new().pragmas().is_empty());
+
+        let config = DbConfig::new()
+            .synchronous(Synchronous::Normal)
+            .foreign_keys(true)
+            .cache_size(-2000)
+            .temp_store(TempStore::Memory);
+        assert_eq!(
+            config.pragmas(),
+            vec![
+                "PRAGMA synchronous = NORMAL",
+                "PRAGMA foreign_keys = ON",
+                "PRAGMA cache_size = -2000",
+                "PRAGMA temp_store = MEMORY",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_to_dsn() {
+        let config = DbConfig::new()
+            .read_only()
+            .busy_timeout(Duration::from_millis(250));
+        let mut dsn = Dsn::parse("sqlite:rsorm_test?busy_timeout=10").unwrap();
+        config.apply_to_dsn(&mut dsn);
+        assert_eq!(dsn.mode, Mode::ReadOnly);
+        assert_eq!(dsn.busy_timeout, Some(250));
+
+        let mut dsn = Dsn::parse(":memory:").unwrap();
+        config.apply_to_dsn(&mut dsn);
+        assert_eq!(dsn.mode, Mode::Memory);
+    }
+}
@@ -0,0 +1,425 @@
+use crate::error::Error;
+use serde::de::{self, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+use serde::forward_to_deserialize_any;
+
+///
+/// Deserializes the current row of a sqlite statement.<br>
+/// Struct fields are matched against columns by name, tuples and sequences by position.<br>
+/// `columns` holds the names of the columns visible to this deserializer, which start at
+/// index `offset` of the statement.
+///
+pub(crate) struct RowDeserializer<'r, 'l> {
+    stmt: &'r sqlite::Statement<'l>,
+    columns: &'r [String],
+    offset: usize,
+}
+
+impl<'r, 'l> RowDeserializer<'r, 'l> {
+    pub(crate) fn new(stmt: &'r sqlite::Statement<'l>, columns: &'r [String]) -> Self {
+        RowDeserializer {
+            stmt,
+            columns,
+            offset: 0,
+        }
+    }
+
+    /// a deserializer whose `columns` start at index `offset` of the statement
+    pub(crate) fn with_offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    fn column(&self, i: usize) -> ColumnDeserializer<'r, 'l> {
+        ColumnDeserializer {
+            stmt: self.stmt,
+            index: self.offset + i,
+        }
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c == name)
+    }
+
+    fn single_column(&self) -> Result<ColumnDeserializer<'r, 'l>, Error> {
+        if self.columns.len() != 1 {
+            return Err(Error::Decode(format!(
+                "expected a single column, found {}",
+                self.columns.len()
+            )));
+        }
+        Ok(self.column(0))
+    }
+}
+
+macro_rules! forward_to_single_column {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                self.single_column()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de, 'r, 'l> de::Deserializer<'de> for RowDeserializer<'r, 'l> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if self.columns.len() == 1 {
+            return self.column(0).deserialize_any(visitor);
+        }
+        self.deserialize_map(visitor)
+    }
+
+    forward_to_single_column! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_f32 deserialize_f64 deserialize_char deserialize_str deserialize_string
+        deserialize_bytes deserialize_byte_buf deserialize_unit
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let all_null = (0..self.columns.len())
+            .all(|i| self.stmt.column_type(self.offset + i) == sqlite::Type::Null);
+        if all_null {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(RowSeq { row: self, next: 0 })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let columns = (0..self.columns.len()).collect();
+        visitor.visit_map(RowMap {
+            row: self,
+            columns,
+            next: 0,
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        // only visit the columns the struct asks for, serde reports any that are missing
+        let columns = fields.iter().filter_map(|f| self.position(f)).collect();
+        visitor.visit_map(RowMap {
+            row: self,
+            columns,
+            next: 0,
+        })
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.single_column()?
+            .deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+}
+
+struct RowSeq<'r, 'l> {
+    row: RowDeserializer<'r, 'l>,
+    next: usize,
+}
+
+impl<'de, 'r, 'l> SeqAccess<'de> for RowSeq<'r, 'l> {
+    type Error = Error;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Result<Option<S::Value>, Error> {
+        if self.next >= self.row.columns.len() {
+            return Ok(None);
+        }
+        let column = self.row.column(self.next);
+        self.next += 1;
+        seed.deserialize(column).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.row.columns.len() - self.next)
+    }
+}
+
+struct RowMap<'r, 'l> {
+    row: RowDeserializer<'r, 'l>,
+    columns: Vec<usize>,
+    next: usize,
+}
+
+impl<'de, 'r, 'l> MapAccess<'de> for RowMap<'r, 'l> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.columns.get(self.next) {
+            Some(&i) => {
+                let name: &str = &self.row.columns[i];
+                seed.deserialize(name.into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let column = self.row.column(self.columns[self.next]);
+        self.next += 1;
+        seed.deserialize(column)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.columns.len() - self.next)
+    }
+}
+
+///
+/// Deserializes a single column of the current row.<br>
+/// Values are read with the type the visitor asks for, so sqlite's own conversions apply
+/// when a column's storage class differs from the field type.
+///
+struct ColumnDeserializer<'r, 'l> {
+    stmt: &'r sqlite::Statement<'l>,
+    index: usize,
+}
+
+impl<'r, 'l> ColumnDeserializer<'r, 'l> {
+    fn kind(&self) -> sqlite::Type {
+        self.stmt.column_type(self.index)
+    }
+
+    fn integer(&self) -> Result<i64, Error> {
+        if self.kind() == sqlite::Type::Null {
+            return Err(de::Error::invalid_type(de::Unexpected::Unit, &"an integer"));
+        }
+        Ok(self.stmt.read::<i64>(self.index)?)
+    }
+
+    fn float(&self) -> Result<f64, Error> {
+        if self.kind() == sqlite::Type::Null {
+            return Err(de::Error::invalid_type(de::Unexpected::Unit, &"a float"));
+        }
+        Ok(self.stmt.read::<f64>(self.index)?)
+    }
+
+    fn string(&self) -> Result<String, Error> {
+        if self.kind() == sqlite::Type::Null {
+            return Err(de::Error::invalid_type(de::Unexpected::Unit, &"a string"));
+        }
+        Ok(self.stmt.read::<String>(self.index)?)
+    }
+}
+
+macro_rules! deserialize_integer {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                visitor.$visit(self.integer()?)
+            }
+        )*
+    };
+}
+
+impl<'de, 'r, 'l> de::Deserializer<'de> for ColumnDeserializer<'r, 'l> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.kind() {
+            sqlite::Type::Integer => visitor.visit_i64(self.stmt.read::<i64>(self.index)?),
+            sqlite::Type::Float => visitor.visit_f64(self.stmt.read::<f64>(self.index)?),
+            sqlite::Type::String => visitor.visit_string(self.stmt.read::<String>(self.index)?),
+            sqlite::Type::Binary => visitor.visit_byte_buf(self.stmt.read::<Vec<u8>>(self.index)?),
+            sqlite::Type::Null => visitor.visit_unit(),
+        }
+    }
+
+    deserialize_integer! {
+        deserialize_i8 => visit_i64,
+        deserialize_i16 => visit_i64,
+        deserialize_i32 => visit_i64,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_i64,
+        deserialize_u16 => visit_i64,
+        deserialize_u32 => visit_i64,
+        deserialize_u64 => visit_i64,
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_bool(self.integer()? != 0)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_f64(self.float()?)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_f64(self.float()?)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_string(self.string()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_string(self.string()?)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_string(self.string()?)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_byte_buf(self.stmt.read::<Vec<u8>>(self.index)?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_byte_buf(self.stmt.read::<Vec<u8>>(self.index)?)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if self.kind() == sqlite::Type::Null {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        // unit variants are stored by name
+        visitor.visit_enum(self.string()?.into_deserializer())
+    }
+
+    forward_to_deserialize_any! {
+        unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::DeserializeOwned;
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Person {
+        name: String,
+        age: u64,
+        nickname: Option<String>,
+    }
+
+    fn first_row<T: DeserializeOwned>(conn: &sqlite::Connection, query: &str) -> T {
+        let mut stmt = conn.prepare(query).unwrap();
+        assert_eq!(stmt.next().unwrap(), sqlite::State::Row);
+        let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+        T::deserialize(RowDeserializer::new(&stmt, &columns)).unwrap()
+    }
+
+    fn setup() -> sqlite::Connection {
+        let conn = sqlite::open(":memory:").unwrap();
+        conn.execute(
+            "CREATE TABLE Person (age INTEGER, nickname TEXT, name TEXT);
+             INSERT INTO Person VALUES (31, NULL, 'say \"hi\" \\ ''bye''');",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_struct_by_column_name() {
+        let conn = setup();
+        let p: Person = first_row(&conn, "SELECT * FROM Person");
+        assert_eq!(
+            p,
+            Person {
+                name: String::from("say \"hi\" \\ 'bye'"),
+                age: 31,
+                nickname: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_tuple_and_scalar() {
+        let conn = setup();
+        let t: (u64, Option<String>) = first_row(&conn, "SELECT age, nickname FROM Person");
+        assert_eq!(t, (31, None));
+        let count: u64 = first_row(&conn, "SELECT COUNT(*) FROM Person");
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_missing_column() {
+        let conn = setup();
+        let mut stmt = conn.prepare("SELECT name FROM Person").unwrap();
+        stmt.next().unwrap();
+        let columns = vec![String::from("name")];
+        let result = Person::deserialize(RowDeserializer::new(&stmt, &columns));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,214 @@
+use crate::error::{Error, Result};
+use std::path::{Path, PathBuf};
+
+const MEMORY: &str = ":memory:";
+
+///
+/// How a database file is opened, set with the `mode` dsn option.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Mode {
+    /// read-only, the file must exist
+    ReadOnly,
+    /// read-write, the file must exist
+    ReadWrite,
+    /// read-write, creating the file if it doesn't exist
+    ReadWriteCreate,
+    /// a private in-memory database
+    Memory,
+}
+
+///
+/// A parsed data source name.<br>
+/// Either a plain path, `:memory:`, or a `sqlite:` url such as
+/// `sqlite:///opt/databases/mydb.sq3?mode=ro&busy_timeout=500`.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Dsn {
+    pub(crate) path: PathBuf,
+    pub(crate) mode: Mode,
+    pub(crate) shared_cache: bool,
+    pub(crate) busy_timeout: Option<usize>,
+}
+
+impl Dsn {
+    pub(crate) fn parse<P: AsRef<Path>>(dsn: P) -> Result<Dsn> {
+        let path = dsn.as_ref();
+        let url = match path.to_str() {
+            Some(s) if s.starts_with("sqlite:") => &s["sqlite:".len()..],
+            _ => return Ok(Dsn::file(path.to_path_buf())),
+        };
+
+        let (location, query) = match url.find('?') {
+            Some(i) => (&url[..i], Some(&url[i + 1..])),
+            None => (url, None),
+        };
+        // `sqlite:///abs/path` and `sqlite://rel/path` carry an empty authority
+        let location = location.strip_prefix("//").unwrap_or(location);
+        let mut dsn = Dsn::file(PathBuf::from(percent_decode(location)?));
+
+        for pair in query.into_iter().flat_map(|q| q.split('&')) {
+            if pair.is_empty() {
+                continue;
+            }
+            let (key, value) = match pair.find('=') {
+                Some(i) => (&pair[..i], &pair[i + 1..]),
+                None => (pair, ""),
+            };
+            match (key, value) {
+                ("mode", "ro") => dsn.mode = Mode::ReadOnly,
+                ("mode", "rw") => dsn.mode = Mode::ReadWrite,
+                ("mode", "rwc") => dsn.mode = Mode::ReadWriteCreate,
+                ("mode", "memory") => dsn.mode = Mode::Memory,
+                ("cache", "shared") => dsn.shared_cache = true,
+                ("cache", "private") => dsn.shared_cache = false,
+                ("busy_timeout", ms) => {
+                    dsn.busy_timeout = Some(ms.parse().map_err(|_| {
+                        Error::InvalidDsn(format!("busy_timeout must be milliseconds: {}", ms))
+                    })?)
+                }
+                _ => return Err(Error::InvalidDsn(format!("unsupported option: {}", pair))),
+            }
+        }
+
+        if dsn.path.as_os_str().is_empty() && dsn.mode != Mode::Memory {
+            return Err(Error::InvalidDsn(String::from("missing database path")));
+        }
+        if dsn.path == Path::new(MEMORY) {
+            dsn.mode = Mode::Memory;
+        }
+        Ok(dsn)
+    }
+
+    fn file(path: PathBuf) -> Dsn {
+        let mode = if path == Path::new(MEMORY) {
+            Mode::Memory
+        } else {
+            Mode::ReadWriteCreate
+        };
+        Dsn {
+            path,
+            mode,
+            shared_cache: false,
+            busy_timeout: None,
+        }
+    }
+
+    ///
+    /// Opens a connection as described by the dsn
+    ///
+    pub(crate) fn connect(&self) -> Result<sqlite::Connection> {
+        let flags = sqlite::OpenFlags::new();
+        let flags = match self.mode {
+            Mode::ReadOnly => flags.set_read_only(),
+            Mode::ReadWrite => flags.set_read_write(),
+            Mode::ReadWriteCreate | Mode::Memory => flags.set_read_write().set_create(),
+        };
+
+        let mut conn = if self.shared_cache {
+            // shared cache can only be requested through a uri filename
+            if !uri_filenames_enabled()? {
+                return Err(Error::InvalidDsn(String::from(
+                    "cache=shared requires sqlite to be built with uri filename support",
+                )));
+            }
+            sqlite::Connection::open_with_flags(self.shared_cache_uri()?, flags)?
+        } else if self.mode == Mode::Memory {
+            sqlite::Connection::open_with_flags(MEMORY, flags)?
+        } else {
+            sqlite::Connection::open_with_flags(&self.path, flags)?
+        };
+
+        if let Some(ms) = self.busy_timeout {
+            conn.set_busy_timeout(ms)?;
+        }
+        Ok(conn)
+    }
+
+    fn shared_cache_uri(&self) -> Result<String> {
+        if self.mode == Mode::Memory {
+            return Ok(String::from("file::memory:?cache=shared"));
+        }
+        match self.path.to_str() {
+            Some(path) => Ok(format!(
+                "file:{}?cache=shared",
+                path.replace('%', "%25")
+                    .replace('?', "%3f")
+                    .replace('#', "%23")
+            )),
+            None => Err(Error::InvalidDsn(String::from("path is not valid utf-8"))),
+        }
+    }
+}
+
+fn uri_filenames_enabled() -> Result<bool> {
+    let conn = sqlite::open(MEMORY)?;
+    let mut stmt = conn.prepare("SELECT sqlite_compileoption_used('USE_URI')")?;
+    stmt.next()?;
+    Ok(stmt.read::<i64>(0)? == 1)
+}
+
+fn percent_decode(s: &str) -> Result<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s
+                .get(i + 1..i + 3)
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+                .ok_or_else(|| Error::InvalidDsn(format!("invalid percent-encoding in {}", s)))?;
+            out.push(hex);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).map_err(|_| Error::InvalidDsn(format!("path is not valid utf-8: {}", s)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_paths() {
+        let dsn = Dsn::parse("rsorm_test").unwrap();
+        assert_eq!(dsn.path, PathBuf::from("rsorm_test"));
+        assert_eq!(dsn.mode, Mode::ReadWriteCreate);
+
+        let dsn = Dsn::parse(PathBuf::from("/opt/databases/mydb.sq3")).unwrap();
+        assert_eq!(dsn.path, PathBuf::from("/opt/databases/mydb.sq3"));
+
+        assert_eq!(Dsn::parse(":memory:").unwrap().mode, Mode::Memory);
+    }
+
+    #[test]
+    fn test_sqlite_urls() {
+        let dsn = Dsn::parse("sqlite:/opt/databases/mydb.sq3").unwrap();
+        assert_eq!(dsn.path, PathBuf::from("/opt/databases/mydb.sq3"));
+
+        let dsn =
+            Dsn::parse("sqlite:///opt/my%20db.sq3?mode=ro&cache=shared&busy_timeout=250").unwrap();
+        assert_eq!(dsn.path, PathBuf::from("/opt/my db.sq3"));
+        assert_eq!(dsn.mode, Mode::ReadOnly);
+        assert!(dsn.shared_cache);
+        assert_eq!(dsn.busy_timeout, Some(250));
+
+        assert_eq!(Dsn::parse("sqlite::memory:").unwrap().mode, Mode::Memory);
+        assert_eq!(
+            Dsn::parse("sqlite:?mode=memory").unwrap().mode,
+            Mode::Memory
+        );
+    }
+
+    #[test]
+    fn test_invalid_urls() {
+        assert!(Dsn::parse("sqlite:db?mode=bogus").is_err());
+        assert!(Dsn::parse("sqlite:db?busy_timeout=soon").is_err());
+        assert!(Dsn::parse("sqlite:db?journal=wal").is_err());
+        assert!(Dsn::parse("sqlite:").is_err());
+        assert!(Dsn::parse("sqlite:my%zzdb").is_err());
+    }
+}
@@ -0,0 +1,329 @@
+use serde::{de, ser};
+use std::fmt;
+use std::time::Duration;
+
+/// sqlite's primary result codes for a locked database
+const SQLITE_BUSY: isize = 5;
+const SQLITE_LOCKED: isize = 6;
+/// sqlite's primary result code for a failed constraint
+const SQLITE_CONSTRAINT: isize = 19;
+
+///
+/// The error type returned by every fallible rsorm operation.
+///
+#[derive(Debug)]
+pub enum Error {
+    /// The table has not been created on this DB
+    UnknownTable(String),
+    /// The column is not part of the table's schema
+    InvalidColumn { table: String, column: String },
+    /// The type can not be stored by rsorm
+    UnsupportedType { column: String, ty: String },
+    /// An error reported by sqlite, with its result code when one is available
+    Sqlite {
+        code: Option<isize>,
+        message: String,
+    },
+    /// A row could not be converted into the requested type
+    Decode(String),
+    /// A model's `Serialize` implementation failed, with the column it failed on when known
+    Serialize { column: String, message: String },
+    /// No record of the table matched a lookup that expects one
+    NotFound(String),
+    /// More than one record of the table matched a lookup that expects at most one
+    TooManyRows(String),
+    /// A delete or update on the table was given no conditions,
+    /// `DB::delete_all` empties a table explicitly
+    EmptyCondition(String),
+    /// A UNIQUE, NOT NULL, CHECK or FOREIGN KEY constraint failed
+    Constraint(ConstraintViolation),
+    /// The dsn passed to `DB::open` could not be parsed
+    InvalidDsn(String),
+    /// Rows of a batch insert failed, nothing from the batch was written
+    Batch(Vec<RowFailure>),
+    /// No pooled connection was returned within the pool's checkout timeout
+    PoolTimeout(Duration),
+    /// Every attempt allowed by the `RetryPolicy` failed because the database was locked,
+    /// `error` is the last failure
+    RetriesExhausted { attempts: u32, error: Box<Error> },
+    /// The worker thread of an `AsyncDb` stopped before the operation finished.<br>
+    /// Only returned with the `async` feature, but always present so matches on `Error`
+    /// compile the same with and without it.
+    WorkerStopped,
+    /// A transaction was committed or rolled back while one nested inside it was still open,
+    /// with the depth of the transaction and the current depth
+    TransactionOrder(usize, usize),
+}
+
+///
+/// A row of a batch insert that could not be written, `row` is its index in the batch.
+///
+#[derive(Debug)]
+pub struct RowFailure {
+    pub row: usize,
+    pub error: Error,
+}
+
+///
+/// The kind of constraint that rejected a write.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConstraintKind {
+    Unique,
+    NotNull,
+    Check,
+    ForeignKey,
+    /// Any other constraint sqlite reports, such as one raised by a trigger
+    Other,
+}
+
+///
+/// A failed constraint, parsed from sqlite's error message.<br>
+/// `columns` is empty for CHECK and FOREIGN KEY failures, which sqlite doesn't attribute to columns.<br>
+/// `table` falls back to the table being written when sqlite doesn't name one.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConstraintViolation {
+    pub kind: ConstraintKind,
+    pub table: String,
+    pub columns: Vec<String>,
+    pub message: String,
+}
+
+impl ConstraintViolation {
+    fn parse(message: String) -> ConstraintViolation {
+        let prefixes = [
+            ("UNIQUE constraint failed: ", ConstraintKind::Unique),
+            ("NOT NULL constraint failed: ", ConstraintKind::NotNull),
+            ("CHECK constraint failed", ConstraintKind::Check),
+            ("FOREIGN KEY constraint failed", ConstraintKind::ForeignKey),
+        ];
+        let mut violation = ConstraintViolation {
+            kind: ConstraintKind::Other,
+            table: String::new(),
+            columns: Vec::new(),
+            message: String::new(),
+        };
+
+        for (prefix, kind) in prefixes.iter() {
+            if !message.starts_with(prefix) {
+                continue;
+            }
+            violation.kind = *kind;
+            if *kind == ConstraintKind::Unique || *kind == ConstraintKind::NotNull {
+                // the remainder lists the columns as `table.column, table.column`
+                for qualified in message[prefix.len()..].split(", ") {
+                    if let Some(dot) = qualified.find('.') {
+                        violation.table = qualified[..dot].to_string();
+                        violation.columns.push(qualified[dot + 1..].to_string());
+                    }
+                }
+            }
+            break;
+        }
+
+        violation.message = message;
+        violation
+    }
+}
+
+///
+/// Shorthand for a `Result` with an rsorm `Error`.
+///
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownTable(table) => write!(f, "DB does not contain table: {}", table),
+            Error::InvalidColumn { table, column } => {
+                write!(f, "Invalid column {} for db table: {}", column, table)
+            }
+            Error::UnsupportedType { column, ty } => write!(
+                f,
+                "Unsupported type {} for column {}, RSORM models can only contain the following types: u64, f64, and String",
+                ty, column
+            ),
+            Error::Sqlite {
+                code: Some(code),
+                message,
+            } => write!(f, "{} (code {})", message, code),
+            Error::Sqlite { code: None, message } => f.write_str(message),
+            Error::Decode(message) => write!(f, "Could not decode row: {}", message),
+            Error::Serialize { column, message } if column.is_empty() => {
+                write!(f, "Could not serialize model: {}", message)
+            }
+            Error::Serialize { column, message } => {
+                write!(f, "Could not serialize column {}: {}", column, message)
+            }
+            Error::NotFound(table) => write!(f, "No record found in table: {}", table),
+            Error::TooManyRows(table) => {
+                write!(f, "More than one record found in table: {}", table)
+            }
+            Error::EmptyCondition(table) => write!(
+                f,
+                "No conditions given to delete or update in table: {}",
+                table
+            ),
+            Error::Constraint(violation) => f.write_str(&violation.message),
+            Error::InvalidDsn(message) => write!(f, "Invalid dsn: {}", message),
+            Error::Batch(failures) => match failures.first() {
+                Some(first) => write!(
+                    f,
+                    "{} rows of the batch failed, first at row {}: {}",
+                    failures.len(),
+                    first.row,
+                    first.error
+                ),
+                None => f.write_str("The batch failed"),
+            },
+            Error::PoolTimeout(timeout) => write!(
+                f,
+                "Timed out after {:?} waiting for a pooled connection",
+                timeout
+            ),
+            Error::RetriesExhausted { attempts, error } => {
+                write!(f, "Gave up after {} attempts: {}", attempts, error)
+            }
+            Error::WorkerStopped => f.write_str("The async worker thread stopped"),
+            Error::TransactionOrder(depth, open) => write!(
+                f,
+                "Transaction at depth {} finished while depth {} is still open",
+                depth, open
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    ///
+    /// Whether the operation failed because another connection holds a lock on the database
+    ///
+    pub fn is_busy(&self) -> bool {
+        match self {
+            Error::Sqlite {
+                code: Some(code), ..
+            } => *code == SQLITE_BUSY || *code == SQLITE_LOCKED,
+            Error::RetriesExhausted { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// names `table` as the table of a constraint violation that sqlite didn't attribute to one
+    pub(crate) fn in_table(self, table: &str) -> Error {
+        match self {
+            Error::Constraint(mut violation) => {
+                if violation.table.is_empty() {
+                    violation.table = table.to_string();
+                }
+                Error::Constraint(violation)
+            }
+            e => e,
+        }
+    }
+
+    ///
+    /// Attributes an `UnsupportedType` or `Serialize` error to `column`,
+    /// other errors are returned unchanged.<br>
+    /// Used where a value is converted before the column it belongs to is known.
+    ///
+    pub fn in_column(self, column: &str) -> Error {
+        match self {
+            Error::UnsupportedType { ty, .. } => Error::UnsupportedType {
+                column: column.to_string(),
+                ty,
+            },
+            Error::Serialize { message, .. } => Error::Serialize {
+                column: column.to_string(),
+                message,
+            },
+            e => e,
+        }
+    }
+}
+
+impl From<sqlite::Error> for Error {
+    fn from(e: sqlite::Error) -> Self {
+        let message = e.message.unwrap_or_else(|| String::from("an SQLite error"));
+        match e.code {
+            Some(SQLITE_CONSTRAINT) => Error::Constraint(ConstraintViolation::parse(message)),
+            code => Error::Sqlite { code, message },
+        }
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::Decode(msg.to_string())
+    }
+}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::Serialize {
+            column: String::new(),
+            message: msg.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn constraint(message: &str) -> ConstraintViolation {
+        let e = sqlite::Error {
+            code: Some(SQLITE_CONSTRAINT),
+            message: Some(message.to_string()),
+        };
+        match Error::from(e) {
+            Error::Constraint(violation) => violation,
+            other => panic!("expected a constraint error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_unique() {
+        let v = constraint("UNIQUE constraint failed: Person.name, Person.birthday");
+        assert_eq!(v.kind, ConstraintKind::Unique);
+        assert_eq!(v.table, "Person");
+        assert_eq!(v.columns, vec!["name", "birthday"]);
+    }
+
+    #[test]
+    fn test_parse_not_null() {
+        let v = constraint("NOT NULL constraint failed: Person.age");
+        assert_eq!(v.kind, ConstraintKind::NotNull);
+        assert_eq!(v.table, "Person");
+        assert_eq!(v.columns, vec!["age"]);
+    }
+
+    #[test]
+    fn test_parse_unattributed() {
+        let v = constraint("CHECK constraint failed: age_positive");
+        assert_eq!(v.kind, ConstraintKind::Check);
+        assert!(v.columns.is_empty());
+
+        let v = constraint("FOREIGN KEY constraint failed");
+        assert_eq!(v.kind, ConstraintKind::ForeignKey);
+        match Error::Constraint(v).in_table("Person") {
+            Error::Constraint(v) => assert_eq!(v.table, "Person"),
+            other => panic!("expected a constraint error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_serialize_in_column() {
+        let e = <Error as ser::Error>::custom("bad value");
+        assert_eq!(e.to_string(), "Could not serialize model: bad value");
+        match e.in_column("age") {
+            Error::Serialize { column, message } => {
+                assert_eq!(column, "age");
+                assert_eq!(message, "bad value");
+            }
+            other => panic!("expected a serialize error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use crate::cache::CachedStatement;
+use crate::error::Error;
+use crate::row::RowRef;
+
+///
+/// A lazy iterator over the records returned by a select.<br>
+/// Backed by a live sqlite statement, so only the current row is held in memory.<br>
+/// Returned by `DB::iter_all` and `DB::iter_where`.
+///
+pub struct RowIter<'a, T> {
+    stmt: CachedStatement<'a>,
+    columns: Vec<String>,
+    done: bool,
+    decode: Decoder<T>,
+    split: usize,
+}
+
+/// builds a record from a row, given the index of the first column of a joined model
+pub(crate) type Decoder<T> = fn(&RowRef, usize) -> Result<T, Error>;
+
+impl<'a, T> RowIter<'a, T> {
+    pub(crate) fn with_decoder(
+        stmt: CachedStatement<'a>,
+        decode: Decoder<T>,
+        split: usize,
+    ) -> Self {
+        let columns = stmt.column_names().into_iter().map(String::from).collect();
+        RowIter {
+            stmt,
+            columns,
+            done: false,
+            decode,
+            split,
+        }
+    }
+
+    fn read_row(&self) -> Result<T, Error> {
+        (self.decode)(&RowRef::new(&self.stmt, &self.columns), self.split)
+    }
+}
+
+impl<'a, T> Iterator for RowIter<'a, T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.stmt.next() {
+            Ok(sqlite::State::Row) => Some(self.read_row()),
+            Ok(sqlite::State::Done) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(Error::from(e)))
+            }
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use crate::error::Result;
+use crate::iter::{Decoder, RowIter};
+use crate::query::{Condition, Order};
+use crate::row::{FromRow, RowRef};
+use crate::sqlgen::{JoinClause, Select};
+use crate::value::Value;
+use crate::DB;
+
+///
+/// Declares the key that joins the table of model `Self` with the table of model `B`,
+/// for `Query::join_related` and `Query::left_join_related`.<br>
+/// Usually a foreign key, such as a pet's `owner_id` referring to a person's `id`.
+/// A model stored as a tree declares `Related<Self>`, with the column referring to a
+/// record's parent first, for `Query::descendants` and `Query::ancestors`.
+///
+pub trait Related<B> {
+    /// the column of `Self` and the column of `B` that hold the same key
+    fn join_columns() -> (&'static str, &'static str);
+}
+
+///
+/// A `Query` on the table of model `A` joined with the table of another model, created with
+/// `Query::join` or `Query::left_join`.<br>
+/// Each record is returned as an `(A, R)` pair, where `R` is the joined model for an inner
+/// join and an `Option` of it for a left join, `None` when nothing joins the record.<br>
+/// The models are read from their own columns, so columns with the same name in both
+/// tables don't collide.
+///
+pub struct Join<'db, A, R> {
+    db: &'db DB,
+    select: Select,
+    decode: Decoder<(A, R)>,
+}
+
+impl<'db, A, R> Join<'db, A, R>
+where
+    A: FromRow,
+{
+    pub(crate) fn new(
+        db: &'db DB,
+        mut select: Select,
+        mut join: JoinClause,
+        decode: Decoder<(A, R)>,
+    ) -> Self {
+        debug_assert!(
+            select.aggregates.is_empty() && select.group_by.is_empty(),
+            "aggregates can't be joined"
+        );
+        // both models are selected column by column, so the joined model's columns start
+        // right after the ones selected for `A`, whatever else the tables hold.
+        // An unknown table selects nothing and is reported when the query runs.
+        if select.columns.is_empty() {
+            select.columns = db.table_columns(&select.table).unwrap_or_default();
+        }
+        join.columns = db.table_columns(&join.table).unwrap_or_default();
+        select.join = Some(join);
+        Join { db, select, decode }
+    }
+
+    fn join(&mut self) -> &mut JoinClause {
+        self.select
+            .join
+            .as_mut()
+            .expect("a join query always has a join")
+    }
+
+    ///
+    /// Only select records of `A` matching `condition`, see `Query::filter`
+    ///
+    pub fn filter<C: Into<Condition>>(mut self, condition: C) -> Self {
+        self.select.filter.push(condition.into());
+        self
+    }
+
+    ///
+    /// Only select joined records matching `condition`, on the joined model's columns.<br>
+    /// For a left join this also drops the records of `A` that have no joined record.
+    ///
+    pub fn filter_joined<C: Into<Condition>>(mut self, condition: C) -> Self {
+        self.join().filter.push(condition.into());
+        self
+    }
+
+    ///
+    /// Sort by a column of `A` ascending, after any earlier orderings
+    ///
+    pub fn order_by<C: AsRef<str>>(mut self, column: C) -> Self {
+        self.select
+            .order
+            .push((column.as_ref().to_string(), Order::Asc));
+        self
+    }
+
+    ///
+    /// Sort by a column of `A` descending, after any earlier orderings
+    ///
+    pub fn order_by_desc<C: AsRef<str>>(mut self, column: C) -> Self {
+        self.select
+            .order
+            .push((column.as_ref().to_string(), Order::Desc));
+        self
+    }
+
+    ///
+    /// Sort by a column of the joined model, after the orderings on `A`
+    ///
+    pub fn order_by_joined<C: AsRef<str>>(mut self, column: C, order: Order) -> Self {
+        self.join().order.push((column.as_ref().to_string(), order));
+        self
+    }
+
+    ///
+    /// Select at most `limit` records
+    ///
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.select.limit = Some(limit);
+        self
+    }
+
+    ///
+    /// Skip the first `offset` records
+    ///
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.select.offset = Some(offset);
+        self
+    }
+
+    ///
+    /// The sql this query runs, with a `?` for each bound parameter
+    ///
+    pub fn to_sql(&self) -> String {
+        self.select.to_sql().0
+    }
+
+    ///
+    /// The values bound to the query's parameters, in order
+    ///
+    pub fn params(&self) -> Vec<Value> {
+        self.select.to_sql().1
+    }
+
+    ///
+    /// Run the query, collecting every matching pair
+    ///
+    pub fn fetch(self) -> Result<Vec<(A, R)>> {
+        self.iter()?.collect()
+    }
+
+    ///
+    /// Run the query, reading matching pairs lazily as the iterator is advanced
+    ///
+    pub fn iter(self) -> Result<RowIter<'db, (A, R)>> {
+        // the columns selected for A come first
+        let split = self.select.columns.len();
+        self.db.select_with(&self.select, self.decode, split)
+    }
+}
+
+/// a record of an inner join, split before the joined model's first column
+pub(crate) fn pair<A: FromRow, B: FromRow>(row: &RowRef, split: usize) -> Result<(A, B)> {
+    let (left, right) = row.split(split);
+    Ok((A::from_row(&left)?, B::from_row(&right)?))
+}
+
+/// a record of a left join, where the joined model is `None` when all its columns are null
+pub(crate) fn left_pair<A: FromRow, B: FromRow>(
+    row: &RowRef,
+    split: usize,
+) -> Result<(A, Option<B>)> {
+    let (left, right) = row.split(split);
+    let joined = if right.is_null() {
+        None
+    } else {
+        Some(B::from_row(&right)?)
+    };
+    Ok((A::from_row(&left)?, joined))
+}
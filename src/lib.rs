@@ -1,23 +1,67 @@
-extern crate proc_macro;
+extern crate self as rsorm;
 extern crate serde;
-use serde::de::DeserializeOwned;
-use sqlite;
+use migrate_table::MigrateTable;
+use std::cell::Cell;
 use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, PoisonError, RwLock};
+
+mod aggregate;
+#[cfg(feature = "async")]
+mod async_db;
+mod cache;
+mod column;
+mod config;
+mod de;
+mod dsn;
+mod error;
+mod iter;
+mod join;
+mod pool;
+mod query;
+mod retry;
+mod row;
+mod ser;
+mod split;
+mod sqlgen;
+mod testing;
+mod transaction;
+mod value;
+
+pub use aggregate::{Aggregate, Grouped, Having};
+#[cfg(feature = "async")]
+pub use async_db::{AsyncDb, RowStream};
+pub use column::{Column, Operand, Projection};
+pub use config::{DbConfig, JournalMode, Synchronous, TempStore};
+pub use error::{ConstraintKind, ConstraintViolation, Error, Result, RowFailure};
+pub use iter::RowIter;
+pub use join::{Join, Related};
+pub use migrate_table_derive::cond;
+pub use pool::{DbPool, DbPoolBuilder, PooledDb};
+pub use query::{CompareOp, Condition, Order, Query};
+pub use retry::RetryPolicy;
+pub use row::{FromRow, Row, RowRef, ToRow};
+pub use split::{SplitDb, SplitDbBuilder};
+pub use testing::TestDb;
+pub use transaction::Transaction;
+pub use value::{FromValue, ToValue, Value};
+
+/// the schemas of the tables created on a DB, shared by every connection of a `DbPool`
+pub(crate) type Tables = Arc<RwLock<HashMap<String, Vec<(String, String)>>>>;
 
 ///
 /// A simple **sqlite** ORM.<br>
 /// A SQL Table is defined with a struct derived with the `MigrateTable` trait.<br>
 /// Insertions, Deletions, and Where clauses need to be wrapped with the `sql!` macro.<br>
 ///
-///
-///
-
 pub struct DB {
-    dsn: &'static str,
-    tables: HashMap<String, Vec<(String, String)>>,
-    conn: sqlite::Connection,
+    dsn: String,
+    tables: Tables,
+    conn: cache::CachingConnection,
+    tx_depth: Cell<usize>,
+    retry: Cell<Option<RetryPolicy>>,
 }
- 
+
 impl DB {
     ///
     /// Instantiate a DB isntance
@@ -25,26 +69,117 @@ impl DB {
     /// # Arguments
     ///
     /// * `dsn` - a filepath locating the database file, will create if it doesn't exist.
+    ///   Accepts the same forms as `DB::open`.
     ///
-    pub fn new(dsn: &'static str) -> DB {
-        DB {
-            dsn: dsn,
-            tables: HashMap::new(),
-            conn: sqlite::open(dsn).unwrap(),
-        }
+    pub fn new(dsn: &'static str) -> Result<DB> {
+        DB::open(dsn)
+    }
+
+    ///
+    /// Open a DB instance from a path or a dsn known only at runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `dsn` - one of
+    ///   * a filepath locating the database file, will create if it doesn't exist
+    ///   * `:memory:` for a private in-memory database
+    ///   * a `sqlite:` url such as `sqlite:///opt/databases/mydb.sq3?mode=ro&busy_timeout=500`.
+    ///     Supported options are `mode` (`ro`, `rw`, `rwc` or `memory`), `cache` (`shared` or
+    ///     `private`) and `busy_timeout` in milliseconds.
+    ///
+    pub fn open<P: AsRef<Path>>(dsn: P) -> Result<DB> {
+        DB::open_with(dsn, &DbConfig::default())
+    }
+
+    ///
+    /// Open a private in-memory DB, which is gone once the DB is dropped
+    ///
+    pub fn in_memory() -> Result<DB> {
+        DB::open(":memory:")
+    }
+
+    ///
+    /// Open a DB instance with connection settings such as the journal mode and pragmas.
+    ///
+    /// # Arguments
+    ///
+    /// * `dsn` - the database to open, in any form accepted by `DB::open`
+    /// * `config` - the settings to apply, these take precedence over options in the dsn
+    ///
+    pub fn open_with<P: AsRef<Path>>(dsn: P, config: &DbConfig) -> Result<DB> {
+        let mut parsed = dsn::Dsn::parse(&dsn)?;
+        config.apply_to_dsn(&mut parsed);
+        let conn = parsed.connect()?;
+        config.apply(&conn, parsed.mode == dsn::Mode::Memory)?;
+
+        let capacity = config
+            .statement_cache_capacity
+            .unwrap_or(cache::DEFAULT_CAPACITY);
+        Ok(DB {
+            dsn: dsn.as_ref().to_string_lossy().into_owned(),
+            tables: Tables::default(),
+            conn: cache::CachingConnection::new(conn, capacity),
+            tx_depth: Cell::new(0),
+            retry: Cell::new(config.retry),
+        })
+    }
+
+    ///
+    /// The dsn this DB instance was opened with
+    ///
+    pub fn dsn(&self) -> &str {
+        &self.dsn
+    }
+
+    ///
+    /// The number of prepared statements this DB keeps cached, 32 by default
+    ///
+    pub fn statement_cache_capacity(&self) -> usize {
+        self.conn.statements().capacity()
+    }
+
+    ///
+    /// Set how many prepared statements this DB keeps cached.<br>
+    /// Statements are keyed by their sql text and the least recently used is evicted first.
+    /// A capacity of 0 disables the cache.
+    ///
+    /// # Arguments
+    /// * `capacity` - the maximum number of cached statements
+    ///
+    pub fn set_statement_cache_capacity(&self, capacity: usize) {
+        self.conn.statements().set_capacity(capacity)
+    }
+
+    ///
+    /// The policy for retrying operations that fail because the database is locked, if any
+    ///
+    pub fn retry_policy(&self) -> Option<RetryPolicy> {
+        self.retry.get()
+    }
+
+    ///
+    /// Set how writes and transactions are retried when they fail because another
+    /// connection has the database locked.<br>
+    /// Inserts, updates, deletes and table creation are retried as a whole. Transactions
+    /// take the write lock as they begin, so the beginning and the commit are retried rather
+    /// than the writes inside them. With no policy, sqlite's busy timeout is the only wait.
+    ///
+    /// # Arguments
+    /// * `policy` - the retry policy, or `None` to fail on the first locked attempt
+    ///
+    pub fn set_retry_policy(&self, policy: Option<RetryPolicy>) {
+        self.retry.set(policy)
     }
+
     ///
     /// Create a table to insert into the database.<br>
     /// Requires a struct with the `MigrateTable` trait derived.<br>
     ///
     /// # Arguments
     ///
-    /// * `schema` - the result of `generate_schema()` called on a struct derived with `MigrateTable` 
+    /// * `schema` - the result of `generate_schema()` called on a struct derived with `MigrateTable`
     ///
-    pub fn create_table(
-        &mut self,
-        schema: (String, Vec<(String, String)>),
-    ) -> Result<(), &'static str> {
+    pub fn create_table(&self, schema: (String, Vec<(String, String)>)) -> Result<()> {
         let name = schema.0;
         let fields = schema.1;
         let legal_types: HashSet<String> =
@@ -53,139 +188,467 @@ impl DB {
                 .collect();
         for f in fields.iter() {
             if !legal_types.contains(&f.1) {
-                return Err(
-                    "RSORM models can only contain the following types: u64, f64, and String",
-                );
+                return Err(Error::UnsupportedType {
+                    column: f.0.clone(),
+                    ty: f.1.clone(),
+                });
             }
         }
 
-        let ts = self.table_string(&name, &fields);
-        if self.tables.contains_key(&name) {
+        if self.check_table(&name).is_ok() {
             return Ok(());
         }
-        self.conn.execute(&ts).unwrap();
-        self.tables.insert(name, fields);
+        // the DDL runs, and may wait out a locked database, without holding the registry lock,
+        // which every connection of a pool needs to check its queries.
+        // CREATE TABLE IF NOT EXISTS makes racing another connection harmless.
+        let ts = self.table_string(&name, &fields);
+        self.retrying(|| self.conn.execute(&ts).map_err(Error::from))?;
+        self.tables
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(name)
+            .or_insert(fields);
         Ok(())
     }
-    
+
     ///
     ///Inserts into the specifed table, the data provided
     ///
     /// # Arguments
     /// * `table` - The name of a previously created table, as a string
     /// * `data` - The data that is to be entered into the database.
-    ///    called with the `sql` macro
+    ///   called with the `sql` macro
     ///
-    pub fn insert(&self, table: &str, data: (Vec<String>, Vec<String>)) -> Result<(), String> {
-    
-        if !self.tables.contains_key(table) {
-            return Err(format!("DB does not contain table: {}", table));
-        }
+    pub fn insert(&self, table: &str, data: (Vec<String>, Vec<String>)) -> Result<()> {
+        self.check_columns(table, &data.0)?;
 
-        if self.invalid_fields(table, &data) {
-            return Err(format!("Invalid column/s for db table: {}", table));
-        }
+        let values: Vec<Value> = data.1.iter().map(|l| Value::from_literal(l)).collect();
+        let is = insert_string(table, &data.0, &vec![String::from("?"); values.len()]);
+        self.execute_cached(table, &is, &values)
+    }
 
-        let is = insert_string(table, data);
-        self.conn.execute(&is).unwrap();
-        Ok(())
+    ///
+    /// Inserts a model into the specified table.<br>
+    /// Works with any `serde::Serialize` model, or one that derives `ToRow`.<br>
+    /// Values are bound as statement parameters rather than written into the sql.
+    ///
+    /// # Arguments
+    /// * `table` - The name of a previously created table, as a string
+    /// * `model` - The model to insert, its field names must match the table's columns
+    ///
+    pub fn insert_model<T: ToRow>(&self, table: &str, model: &T) -> Result<()> {
+        self.check_table(table)?;
+
+        let (fields, values): (Vec<String>, Vec<Value>) = model.to_row()?.into_iter().unzip();
+        self.check_columns(table, &fields)?;
+
+        let is = insert_string(table, &fields, &vec![String::from("?"); values.len()]);
+        self.retrying(|| {
+            let mut stmt = self.conn.prepare_cached(&is)?;
+            value::bind_values(&mut stmt, &values)?;
+            stmt.next()?;
+            Ok(())
+        })
+        .map_err(|e| e.in_table(table))
+    }
+
+    ///
+    /// Inserts many models with one prepared statement inside a single transaction.<br>
+    /// Every row is attempted, and if any fail the whole batch is rolled back and
+    /// `Error::Batch` reports each failed row by its index.<br>
+    /// Returns the number of rows inserted.
+    ///
+    /// # Arguments
+    /// * `table` - The name of a previously created table, as a string
+    /// * `models` - The models to insert, their field names must match the table's columns
+    ///
+    pub fn insert_many<T: ToRow>(&self, table: &str, models: &[T]) -> Result<usize> {
+        let mut rows = models.iter().map(|model| model.to_row());
+        let first = match rows.next() {
+            Some(row) => row?,
+            None => return Ok(0),
+        };
+        let fields: Vec<String> = first.iter().map(|(field, _)| field.clone()).collect();
+        let first = Ok(first.into_iter().map(|(_, value)| value).collect());
+
+        let values = rows.map(|row| {
+            let (row_fields, values): (Vec<String>, Vec<Value>) = row?.into_iter().unzip();
+            match row_fields.iter().find(|field| !fields.contains(field)) {
+                Some(field) => Err(Error::InvalidColumn {
+                    table: table.to_string(),
+                    column: field.clone(),
+                }),
+                None if row_fields != fields => Err(Error::Decode(String::from(
+                    "every row of a batch must have the same fields",
+                ))),
+                None => Ok(values),
+            }
+        });
+        self.insert_batch(table, &fields, std::iter::once(first).chain(values))
+    }
+
+    ///
+    /// Inserts many records written with the `sql` macro, with one prepared statement
+    /// inside a single transaction.<br>
+    /// Every row must set the same columns as the first.
+    /// Failures are reported as with `insert_many`.
+    ///
+    /// # Arguments
+    /// * `table` - The name of a previously created table, as a string
+    /// * `rows` - the records to insert, each called with the `sql` macro
+    ///
+    pub fn insert_values(
+        &self,
+        table: &str,
+        rows: Vec<(Vec<String>, Vec<String>)>,
+    ) -> Result<usize> {
+        let fields = match rows.first() {
+            Some((fields, _)) => fields.clone(),
+            None => return Ok(0),
+        };
+
+        let values = rows.into_iter().map(|(row_fields, literals)| {
+            if row_fields != fields {
+                return Err(Error::Decode(String::from(
+                    "every row of a batch must set the same columns",
+                )));
+            }
+            Ok(literals.iter().map(|l| Value::from_literal(l)).collect())
+        });
+        self.insert_batch(table, &fields, values)
+    }
+
+    ///
+    /// Select all records from table that match specified conditions
+    ///
+    /// # Arguments
+    /// * `table` - The name of a previously created table, as a string
+    /// * `data` - the conditions that will be matched against for selection, called with the `sql` macro.
+    ///
+    pub fn select_where<T>(&self, table: &str, data: (Vec<String>, Vec<String>)) -> Result<Vec<T>>
+    where
+        T: FromRow,
+    {
+        self.iter_where(table, data)?.collect()
+    }
+
+    ///
+    /// The only record of a table matching `condition`, `None` when nothing matches.<br>
+    /// Fails with `Error::TooManyRows` when more than one record matches.
+    ///
+    /// # Arguments
+    /// * `table` - The name of a previously created table, as a string
+    /// * `condition` - a `Condition`, or conditions written with the `sql` macro
+    ///
+    pub fn find_one<T: FromRow>(
+        &self,
+        table: &str,
+        condition: impl Into<Condition>,
+    ) -> Result<Option<T>> {
+        self.query_table(table).filter(condition).find_one()
+    }
+
+    ///
+    /// The only record of a table matching `condition`.<br>
+    /// Fails with `Error::NotFound` when nothing matches, and `Error::TooManyRows` when more
+    /// than one record matches.
+    ///
+    /// # Arguments
+    /// * `table` - The name of a previously created table, as a string
+    /// * `condition` - a `Condition`, or conditions written with the `sql` macro
+    ///
+    pub fn get<T: FromRow>(&self, table: &str, condition: impl Into<Condition>) -> Result<T> {
+        self.query_table(table).filter(condition).get()
+    }
+
+    ///
+    /// Whether any record of a table matches `condition`
+    ///
+    /// # Arguments
+    /// * `table` - The name of a previously created table, as a string
+    /// * `condition` - a `Condition`, or conditions written with the `sql` macro
+    ///
+    pub fn exists(&self, table: &str, condition: impl Into<Condition>) -> Result<bool> {
+        self.query_table::<Row>(table).filter(condition).exists()
+    }
+
+    ///
+    /// Select the records of a table matching `condition` without a model, see `Row`
+    ///
+    /// # Arguments
+    /// * `table` - The name of a previously created table, as a string
+    /// * `condition` - a `Condition`, or conditions written with the `sql` macro
+    ///
+    pub fn select_rows(&self, table: &str, condition: impl Into<Condition>) -> Result<Vec<Row>> {
+        self.query_table(table).filter(condition).fetch()
+    }
+
+    ///
+    /// Select all records from specified table
+    ///
+    /// # Arguments
+    /// * `table` - The name of a previously created table, as a string
+    ///
+    pub fn select_all<T>(&self, table: &str) -> Result<Vec<T>>
+    where
+        T: FromRow,
+    {
+        self.iter_all(table)?.collect()
     }
 
     ///
-    /// Select all records from table that match specified conditions 
+    /// Lazily iterate over the records from table that match specified conditions.<br>
+    /// Rows are read from sqlite one at a time as the iterator is advanced.
     ///
     /// # Arguments
     /// * `table` - The name of a previously created table, as a string
-    /// * `object` - a refrence to a generic emoty vector that will be populated with the records returned from the database.
     /// * `data` - the conditions that will be matched against for selection, called with the `sql` macro.
-    /// 
-    pub fn select_where<T>(
+    ///
+    pub fn iter_where<T>(
         &self,
         table: &str,
-        objects: &mut Vec<T>,
         data: (Vec<String>, Vec<String>),
-    ) -> Result<(), String>
+    ) -> Result<RowIter<'_, T>>
     where
-        T: DeserializeOwned,
+        T: FromRow,
     {
-        if !self.tables.contains_key(table) {
-            return Err(format!("DB does not contain table: {}", table));
-        }
+        let mut select = sqlgen::Select::new(table);
+        select.filter.push(Condition::from(data));
+        self.select(&select)
+    }
 
-        if self.invalid_fields(table, &data) {
-            return Err(format!("Invalid column/s for db table: {}", table));
-        }
+    ///
+    /// Lazily iterate over all records from specified table.<br>
+    /// Rows are read from sqlite one at a time as the iterator is advanced.
+    ///
+    /// # Arguments
+    /// * `table` - The name of a previously created table, as a string
+    ///
+    pub fn iter_all<T>(&self, table: &str) -> Result<RowIter<'_, T>>
+    where
+        T: FromRow,
+    {
+        self.select(&sqlgen::Select::new(table))
+    }
 
-        let q_string = where_string(table, data);
-        self.select_query(table, q_string, objects)
+    ///
+    /// Start building a select on the table of model `T`, see `Query`
+    ///
+    pub fn query<T>(&self) -> Query<'_, T>
+    where
+        T: MigrateTable + FromRow,
+    {
+        Query::new(self, &T::generate_schema().0)
     }
 
     ///
-    /// Select all records from specified table 
+    /// The number of records in the table of model `T` matching `condition`
     ///
     /// # Arguments
-    /// * `table` - The name of a previously created table, as a string
-    /// * `object` - a refrence to a generic emoty vector that will be populated with the records returned from the database
-    /// 
-    pub fn select_all<T>(&self, table: &str, objects: &mut Vec<T>) -> Result<(), String>
+    /// * `condition` - a `Condition`, or conditions written with the `sql` macro
+    ///
+    pub fn count<T>(&self, condition: impl Into<Condition>) -> Result<i64>
     where
-        T: DeserializeOwned,
+        T: MigrateTable + FromRow,
     {
-        if !self.tables.contains_key(table) {
-            return Err(format!("DB does not contain table: {}", table));
-        }
+        self.query::<T>().filter(condition).count()
+    }
+
+    ///
+    /// Start building a select on the named table, for models stored under another name
+    ///
+    /// # Arguments
+    /// * `table` - The name of a previously created table, as a string
+    ///
+    pub fn query_table<T: FromRow>(&self, table: &str) -> Query<'_, T> {
+        Query::new(self, table)
+    }
+
+    ///
+    /// Run a select written in sql and read each record into `T`, for queries the query
+    /// builder can't express.<br>
+    /// The sql isn't checked against the tables created on this DB.
+    ///
+    /// # Arguments
+    /// * `sql` - a single sql statement, with a `?` for each parameter
+    /// * `params` - the values bound to the parameters, in order
+    ///
+    pub fn query_as<T: FromRow>(&self, sql: &str, params: &[&dyn ToValue]) -> Result<Vec<T>> {
+        self.iter_as(sql, params)?.collect()
+    }
 
-        let q_string = format!("SELECT * FROM {}", table);
-        self.select_query(table, q_string, objects)
+    ///
+    /// Like `query_as`, reading records lazily as the iterator is advanced
+    ///
+    /// # Arguments
+    /// * `sql` - a single sql statement, with a `?` for each parameter
+    /// * `params` - the values bound to the parameters, in order
+    ///
+    pub fn iter_as<T: FromRow>(
+        &self,
+        sql: &str,
+        params: &[&dyn ToValue],
+    ) -> Result<RowIter<'_, T>> {
+        let values = value::param_values(params)?;
+        let mut stmt = self.conn.prepare_cached(sql)?;
+        value::bind_values(&mut stmt, &values)?;
+        Ok(RowIter::with_decoder(stmt, |row, _| T::from_row(row), 0))
+    }
+
+    ///
+    /// Run a statement written in sql, returning the number of records it inserted,
+    /// updated or deleted.<br>
+    /// The sql isn't checked against the tables created on this DB, and tables it creates
+    /// aren't added to them.
+    ///
+    /// # Arguments
+    /// * `sql` - a single sql statement, with a `?` for each parameter
+    /// * `params` - the values bound to the parameters, in order
+    ///
+    pub fn execute(&self, sql: &str, params: &[&dyn ToValue]) -> Result<usize> {
+        let values = value::param_values(params)?;
+        self.retrying(|| {
+            let before = self.conn.total_change_count();
+            let mut stmt = self.conn.prepare_cached(sql)?;
+            value::bind_values(&mut stmt, &values)?;
+            while stmt.next()? != sqlite::State::Done {}
+            Ok(self.conn.total_change_count() - before)
+        })
     }
 
     ///
-    /// Deletes into from specifed table, all records that match the conditions given.
+    /// Deletes into from specifed table, all records that match the conditions given.<br>
+    /// Fails with `Error::EmptyCondition` when no conditions are given, see `delete_all`.
     ///
     /// # Arguments
     /// * `table` - The name of a prebiously created table, as a string
     /// * `data` - conditons to match for deleting rcords, called with the `sql` macro
     ///
-    pub fn delete(&self, table: &str, data: (Vec<String>, Vec<String>)) -> Result<(), String> {
-      
-        if !self.tables.contains_key(table) {
-            return Err(format!("DB does not contain table: {}", table));
+    pub fn delete(&self, table: &str, data: (Vec<String>, Vec<String>)) -> Result<()> {
+        self.check_columns(table, &data.0)?;
+        if data.0.is_empty() {
+            return Err(Error::EmptyCondition(table.to_string()));
         }
 
-        if self.invalid_fields(table, &data) {
-            return Err(format!("Invalid column/s for db table: {}", table));
+        let (ds, values) = sqlgen::delete(table, &[Condition::from(data)]);
+        self.execute_cached(table, &ds, &values)
+    }
+
+    ///
+    /// Deletes every record from the specified table
+    ///
+    /// # Arguments
+    /// * `table` - The name of a previously created table, as a string
+    ///
+    pub fn delete_all(&self, table: &str) -> Result<()> {
+        self.check_table(table)?;
+
+        let (ds, values) = sqlgen::delete(table, &[]);
+        self.execute_cached(table, &ds, &values)
+    }
+
+    ///
+    /// Updates all records in the specified table that match the conditions given.<br>
+    /// Fails with `Error::EmptyCondition` when no conditions are given.
+    ///
+    /// # Arguments
+    /// * `table` - The name of a previously created table, as a string
+    /// * `data` - the new column values, called with the `sql` macro
+    /// * `conditions` - conditions to match for updating records, called with the `sql` macro
+    ///
+    pub fn update(
+        &self,
+        table: &str,
+        data: (Vec<String>, Vec<String>),
+        conditions: (Vec<String>, Vec<String>),
+    ) -> Result<()> {
+        self.check_columns(table, &data.0)?;
+        self.check_columns(table, &conditions.0)?;
+        if conditions.0.is_empty() {
+            return Err(Error::EmptyCondition(table.to_string()));
         }
 
-        let ds = delete_string(table, data);
-        self.conn.execute(&ds).unwrap();
-        Ok(())
+        let set: Vec<(String, Value)> = data
+            .0
+            .into_iter()
+            .zip(data.1.iter().map(|literal| Value::from_literal(literal)))
+            .collect();
+        let (us, values) = sqlgen::update(table, &set, &Condition::from(conditions));
+        self.execute_cached(table, &us, &values)
+    }
+
+    ///
+    /// Begin a transaction that rolls back unless `commit` is called on the returned guard.<br>
+    /// Called while another transaction is open, this begins a nested SAVEPOINT instead.
+    ///
+    pub fn begin(&self) -> Result<Transaction<'_>> {
+        Transaction::begin(self)
     }
 
-    fn build_struct_json(&self, table: &str, vals: &[String]) -> String {
-        let mut json = String::from("{ ");
-        let fields = self.tables.get(table).unwrap();
-        let mut i = 0;
-        for (ident, ty) in fields {
-            json.push_str("\"");
-            json.push_str(ident);
-            json.push_str("\": ");
-            if *ty == String::from("String") {
-                json.push_str("\"");
+    ///
+    /// Run `f` inside a transaction.<br>
+    /// Commits when `f` returns `Ok`, and rolls back when it returns `Err` or panics.<br>
+    /// Nested calls map to SAVEPOINTs, so an inner failure only undoes the inner writes.
+    ///
+    /// # Arguments
+    /// * `f` - a closure that receives the transaction handle
+    ///
+    pub fn transaction<F, T, E>(&self, f: F) -> std::result::Result<T, E>
+    where
+        F: FnOnce(&Transaction) -> std::result::Result<T, E>,
+        E: From<Error>,
+    {
+        let tx = self.begin()?;
+        match f(&tx) {
+            Ok(value) => {
+                tx.commit()?;
+                Ok(value)
             }
-            json.push_str(&vals[i]);
-            if *ty == String::from("String") {
-                json.push_str("\"");
+            Err(e) => {
+                // the error from `f` is what the caller needs, a failed rollback must not replace it
+                let _ = tx.rollback();
+                Err(e)
             }
-            json.push_str(",");
-            i += 1;
         }
+    }
+
+    fn insert_batch<I>(&self, table: &str, fields: &[String], rows: I) -> Result<usize>
+    where
+        I: Iterator<Item = Result<Vec<Value>>>,
+    {
+        self.check_table(table)?;
+        self.check_columns(table, fields)?;
 
-        json.pop();
-        json.push_str(" }");
-        json
+        let is = insert_string(table, fields, &vec![String::from("?"); fields.len()]);
+        self.transaction(|tx| {
+            let mut stmt = tx.conn.prepare_cached(&is)?;
+            let mut inserted = 0;
+            let mut failures = Vec::new();
+            for (row, values) in rows.enumerate() {
+                let result = values.and_then(|values| {
+                    value::bind_values(&mut stmt, &values)?;
+                    stmt.next()?;
+                    Ok(())
+                });
+                // a failed step is reported again by reset, it is already captured in `result`
+                let _ = stmt.reset();
+                match result {
+                    Ok(()) => inserted += 1,
+                    Err(error) => failures.push(RowFailure {
+                        row,
+                        error: error.in_table(table),
+                    }),
+                }
+            }
+            if failures.is_empty() {
+                Ok(inserted)
+            } else {
+                Err(Error::Batch(failures))
+            }
+        })
     }
 
-    fn table_string(&self, name: &String, fields: &Vec<(String, String)>) -> String {
+    fn table_string(&self, name: &str, fields: &[(String, String)]) -> String {
         let mut values = String::from("");
         for f in fields {
             values.push_str(&format!(" {} {},", f.0, f.1));
@@ -195,88 +658,94 @@ impl DB {
         format!("CREATE TABLE IF NOT EXISTS {} ({} );", name, values)
     }
 
-    fn select_query<T>(
-        &self,
-        table: &str,
-        q_string: String,
-        objects: &mut Vec<T>,
-    ) -> Result<(), String>
+    pub(crate) fn select<T>(&self, select: &sqlgen::Select) -> Result<RowIter<'_, T>>
     where
-        T: DeserializeOwned,
+        T: FromRow,
     {
-        let mut vals: Vec<String> = Vec::new();
-        // this query pattern was taken from the sqlite crate docs: https://docs.rs/sqlite/0.24.1/sqlite/
-        let _stmt = self
-            .conn
-            .iterate(&q_string, |pairs| {
-                for &(_column, value) in pairs.iter() {
-                    vals.push(String::from(value.unwrap()));
-                }
-                true
-            })
-            .unwrap();
+        self.select_with(select, |row, _| T::from_row(row), 0)
+    }
 
-        let chunks = vals.chunks(self.tables.get(table).unwrap().len());
-        for c in chunks {
-            let json: String = self.build_struct_json(table, c);
-            let object: T = serde_json::from_str(&json).unwrap();
-            objects.push(object);
+    pub(crate) fn select_with<T>(
+        &self,
+        select: &sqlgen::Select,
+        decode: iter::Decoder<T>,
+        split: usize,
+    ) -> Result<RowIter<'_, T>> {
+        select.check_values()?;
+        self.check_columns(&select.table, &select.columns())?;
+        if let Some(join) = &select.join {
+            self.check_columns(&join.table, &join.columns())?;
         }
 
-        Ok(())
+        let (q_string, values) = select.to_sql();
+        let mut stmt = self.conn.prepare_cached(&q_string)?;
+        value::bind_values(&mut stmt, &values)?;
+        Ok(RowIter::with_decoder(stmt, decode, split))
     }
 
-    fn invalid_fields(&self, name: &str, data: &(Vec<String>, Vec<String>)) -> bool {
-        let mut invalid = true;
-        let fields = self.tables.get(name).unwrap();
-        for i in 0..(data.0).len() {
-            for f in fields {
-                if f.0 == (data.0)[i] {
-                    invalid = false
-                }
-            }
+    /// the names of a registered table's columns, in the order they were declared
+    pub(crate) fn table_columns(&self, table: &str) -> Result<Vec<String>> {
+        let tables = self.tables.read().unwrap_or_else(PoisonError::into_inner);
+        tables
+            .get(table)
+            .map(|fields| fields.iter().map(|(column, _)| column.clone()).collect())
+            .ok_or_else(|| Error::UnknownTable(table.to_string()))
+    }
 
-            if invalid {
-                return true;
-            }
-        }
-        invalid
+    fn execute_cached(&self, table: &str, sql: &str, values: &[Value]) -> Result<()> {
+        self.retrying(|| {
+            let mut stmt = self.conn.prepare_cached(sql)?;
+            value::bind_values(&mut stmt, values)?;
+            while stmt.next()? != sqlite::State::Done {}
+            Ok(())
+        })
+        .map_err(|e| e.in_table(table))
     }
-}
 
-fn insert_string(name: &str, data: (Vec<String>, Vec<String>)) -> String {
-    let mut fields = String::from("(");
-    let mut values = String::from("(");
-    for i in 0..(data.0).len() {
-        fields.push_str(&format!("{},", (data.0)[i]));
-        values.push_str(&format!("{},", (data.1)[i]));
-    }
-    fields.pop();
-    values.pop();
-    fields.push(')');
-    values.push(')');
-    format!("INSERT INTO {} {} VALUES {}", name, fields, values)
-}
+    /// whether the connection is outside any transaction, including ones begun with raw sql
+    pub(crate) fn is_autocommit(&self) -> bool {
+        // SAFETY: the handle comes from `conn`, which stays open for as long as `self` is borrowed
+        unsafe { sqlite3_sys::sqlite3_get_autocommit(self.conn.as_raw()) != 0 }
+    }
 
-fn delete_string(name: &str, data: (Vec<String>, Vec<String>)) -> String {
-    let conditions = build_conditions(data);
-    format!("DELETE FROM {} WHERE {}", name, conditions)
-}
+    /// runs `op` under the retry policy, statements inside a transaction are never retried alone
+    pub(crate) fn retrying<T, F>(&self, op: F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        let policy = match self.tx_depth.get() {
+            0 => self.retry.get(),
+            _ => None,
+        };
+        retry::run(policy, op)
+    }
 
-fn where_string(name: &str, data: (Vec<String>, Vec<String>)) -> String {
-    let conditions = build_conditions(data);
-    format!("SELECT * FROM {} WHERE {}", name, conditions)
-}
+    fn check_table(&self, name: &str) -> Result<()> {
+        self.check_columns(name, &[])
+    }
 
-fn build_conditions(data: (Vec<String>, Vec<String>)) -> String {
-    let mut conditions = String::from("(");
-    for i in 0..(data.0).len() {
-        conditions.push_str(&format!("{}={} and ", (data.0)[i], (data.1)[i]))
+    fn check_columns(&self, name: &str, columns: &[String]) -> Result<()> {
+        let tables = self.tables.read().unwrap_or_else(PoisonError::into_inner);
+        let fields = tables
+            .get(name)
+            .ok_or_else(|| Error::UnknownTable(name.to_string()))?;
+        match columns.iter().find(|c| !fields.iter().any(|f| f.0 == **c)) {
+            Some(column) => Err(Error::InvalidColumn {
+                table: name.to_string(),
+                column: column.clone(),
+            }),
+            None => Ok(()),
+        }
     }
-    let trunc_val = conditions.len() - 4;
-    conditions.truncate(trunc_val);
-    conditions.push(')');
-    conditions
+}
+
+fn insert_string(name: &str, fields: &[String], values: &[String]) -> String {
+    format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        name,
+        fields.join(","),
+        values.join(",")
+    )
 }
 
 /// macro that parses user options for a `sql!` command <br>
@@ -287,6 +756,9 @@ fn build_conditions(data: (Vec<String>, Vec<String>)) -> String {
 ///
 #[macro_export]
 macro_rules! sql {
+    () => {
+        (Vec::<String>::new(), Vec::<String>::new())
+    };
     ($($x:tt = $y:tt), *) => {
         {
             let mut fields:Vec<String> = Vec::new();
@@ -302,47 +774,29 @@ macro_rules! sql {
 }
 
 #[cfg(test)]
+#[allow(non_camel_case_types, dead_code)]
 mod tests {
     use super::*;
     use migrate_table::MigrateTable;
-    use migrate_table_derive::MigrateTable;
-    use serde::Deserialize;
+    use migrate_table_derive::{FromRow, MigrateTable, ToRow};
+    use serde::{Deserialize, Serialize};
 
-    #[derive(MigrateTable, Deserialize)]
+    #[derive(MigrateTable, Deserialize, Serialize)]
     struct testModel {
         city: String,
         population: u64,
         avg_age: f64,
     }
 
-    // we have to dispatch tests from here to prevent race conditions on the db
+    // each test gets its own database, so they can run in parallel
     // note that for these to pass you will need SQLite installed
-    #[test]
-    fn run_db_tests() {
-        test_create_badtable();
-        test_insert_valid();
-        test_insert_bad();
-        test_insert_badcolumn();
-        test_delete_nonexistent();
-        test_delete_valid();
-        test_delete_badcondition();
-        test_select_all_happy();
-        test_select_all_badtable();
-        test_select_where_happy();
-        test_select_where_badtable();
-        test_select_where_badcolumn();
-    }
-
-    fn setup() -> DB {
-        let mut db = DB::new("rsorm_test");
+    fn setup() -> TestDb {
+        let db = TestDb::new().unwrap();
         db.create_table(testModel::generate_schema()).unwrap();
         db
     }
 
-    fn teardown() {
-        std::fs::remove_file("rsorm_test").unwrap()
-    }
-
+    #[test]
     fn test_create_badtable() {
         #[derive(MigrateTable, Deserialize)]
         struct testBadModel {
@@ -351,11 +805,11 @@ mod tests {
             avg_age: f64,
         }
 
-        let mut db = setup();
+        let db = setup();
         let result = db.create_table(testBadModel::generate_schema());
-        assert!(result.is_err());
-        teardown();
+        assert!(matches!(result, Err(Error::UnsupportedType { .. })));
     }
+    #[test]
     fn test_insert_valid() {
         let db = setup();
         let result = db.insert(
@@ -364,19 +818,27 @@ mod tests {
         );
         assert!(result.is_ok());
 
-        let mut out: Vec<testModel> = Vec::new();
-        db.select_all("testModel", &mut out).unwrap();
+        let out: Vec<testModel> = db.select_all("testModel").unwrap();
         assert_eq!(1, out.len());
-        teardown();
+
+        // values are bound, so quotes in them are stored as written
+        db.insert(
+            "testModel",
+            sql!(city = "it's", population = 1, avg_age = 1.5),
+        )
+        .unwrap();
+        let out: Vec<testModel> = db.select_where("testModel", sql!(population = 1)).unwrap();
+        assert_eq!(out[0].city, "it's");
     }
 
+    #[test]
     fn test_insert_bad() {
         let db = setup();
         let result = db.insert("Model", sql!(name = "Jordan", age = 8, birthday = "idk"));
-        assert!(result.is_err());
-        teardown();
+        assert!(matches!(result, Err(Error::UnknownTable(_))));
     }
 
+    #[test]
     fn test_delete_nonexistent() {
         // this seems bad but sqlite is fine with it so it does not produce an error
         let db = setup();
@@ -385,19 +847,25 @@ mod tests {
             sql!(city = "Gresham", population = 100000, avg_age = 44.3),
         );
         assert!(result.is_ok());
-        teardown();
     }
 
+    #[test]
     fn test_insert_badcolumn() {
         let db = setup();
         let result = db.insert(
             "testModel",
             sql!(bad = "Gresham", population = 100000, avg_age = 44.3),
         );
-        assert!(result.is_err());
-        teardown();
+        assert!(matches!(result, Err(Error::InvalidColumn { .. })));
+
+        let result = db.insert(
+            "testModel",
+            sql!(city = "Gresham", population = 100000, bad = 44.3),
+        );
+        assert!(matches!(result, Err(Error::InvalidColumn { .. })));
     }
 
+    #[test]
     fn test_delete_valid() {
         let db = setup();
         db.insert(
@@ -412,12 +880,11 @@ mod tests {
         );
         assert!(result.is_ok());
 
-        let mut out: Vec<testModel> = Vec::new();
-        db.select_all("testModel", &mut out).unwrap();
+        let out: Vec<testModel> = db.select_all("testModel").unwrap();
         assert_eq!(0, out.len());
-        teardown();
     }
 
+    #[test]
     fn test_delete_badcondition() {
         let db = setup();
         db.insert(
@@ -436,12 +903,33 @@ mod tests {
         );
         assert!(result.is_err());
 
-        let mut out: Vec<testModel> = Vec::new();
-        db.select_all("testModel", &mut out).unwrap();
+        let out: Vec<testModel> = db.select_all("testModel").unwrap();
         assert_eq!(1, out.len());
-        teardown();
     }
 
+    #[test]
+    fn test_delete_empty_condition() {
+        let db = setup();
+        db.insert(
+            "testModel",
+            sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+        )
+        .unwrap();
+
+        let result = db.delete("testModel", sql!());
+        assert!(matches!(result, Err(Error::EmptyCondition(_))));
+        let result = db.update("testModel", sql!(population = 1), sql!());
+        assert!(matches!(result, Err(Error::EmptyCondition(_))));
+        let out: Vec<testModel> = db.select_all("testModel").unwrap();
+        assert_eq!(out[0].population, 100000);
+
+        db.delete_all("testModel").unwrap();
+        assert!(db.select_all::<testModel>("testModel").unwrap().is_empty());
+        let result = db.delete_all("idontexist");
+        assert!(matches!(result, Err(Error::UnknownTable(_))));
+    }
+
+    #[test]
     fn test_select_all_happy() {
         let db = setup();
         db.insert(
@@ -455,13 +943,12 @@ mod tests {
         )
         .unwrap();
 
-        let mut out: Vec<testModel> = Vec::new();
-        let result = db.select_all("testModel", &mut out);
+        let result = db.select_all::<testModel>("testModel");
         assert!(result.is_ok());
-        assert_eq!(2, out.len());
-        teardown();
+        assert_eq!(2, result.unwrap().len());
     }
 
+    #[test]
     fn test_select_all_badtable() {
         let db = setup();
         db.insert(
@@ -475,12 +962,11 @@ mod tests {
         )
         .unwrap();
 
-        let mut out: Vec<testModel> = Vec::new();
-        let result = db.select_all("idontexist", &mut out);
+        let result = db.select_all::<testModel>("idontexist");
         assert!(result.is_err());
-        teardown();
     }
 
+    #[test]
     fn test_select_where_happy() {
         let db = setup();
         db.insert(
@@ -494,17 +980,17 @@ mod tests {
         )
         .unwrap();
 
-        let mut out: Vec<testModel> = Vec::new();
-        let result = db.select_where("testModel", &mut out, sql!(city = "Gresham"));
+        let result = db.select_where::<testModel>("testModel", sql!(city = "Gresham"));
         assert!(result.is_ok());
+        let out = result.unwrap();
         assert_eq!(1, out.len());
 
         for c in out {
             assert_eq!(c.city, "Gresham");
         }
-        teardown();
     }
 
+    #[test]
     fn test_select_where_badtable() {
         let db = setup();
         db.insert(
@@ -518,12 +1004,11 @@ mod tests {
         )
         .unwrap();
 
-        let mut out: Vec<testModel> = Vec::new();
-        let result = db.select_where("idontexist", &mut out, sql!(avg_age = 62.3));
+        let result = db.select_where::<testModel>("idontexist", sql!(avg_age = 62.3));
         assert!(result.is_err());
-        teardown();
     }
 
+    #[test]
     fn test_select_where_badcolumn() {
         let db = setup();
         db.insert(
@@ -537,9 +1022,1414 @@ mod tests {
         )
         .unwrap();
 
-        let mut out: Vec<testModel> = Vec::new();
-        let result = db.select_where("testModel", &mut out, sql!(bad = 62.3));
-        assert!(result.is_err());
-        teardown();
+        let result = db.select_where::<testModel>("testModel", sql!(bad = 62.3));
+        assert!(matches!(result, Err(Error::InvalidColumn { .. })));
+    }
+
+    #[test]
+    fn test_iter_all_streams() {
+        let db = setup();
+        db.insert(
+            "testModel",
+            sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+        )
+        .unwrap();
+        db.insert(
+            "testModel",
+            sql!(city = "Sandy", population = 10000, avg_age = 62.3),
+        )
+        .unwrap();
+
+        let mut iter = db.iter_all::<testModel>("testModel").unwrap();
+        let first = iter.next().unwrap().unwrap();
+        assert_eq!(first.city, "Gresham");
+        let second = iter.next().unwrap().unwrap();
+        assert_eq!(second.population, 10000);
+        assert!(iter.next().is_none());
+        assert!(iter.next().is_none());
+        drop(iter);
+    }
+
+    #[test]
+    fn test_iter_where_happy() {
+        let db = setup();
+        db.insert(
+            "testModel",
+            sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+        )
+        .unwrap();
+        db.insert(
+            "testModel",
+            sql!(city = "Sandy", population = 10000, avg_age = 62.3),
+        )
+        .unwrap();
+
+        let cities: Vec<String> = db
+            .iter_where::<testModel>("testModel", sql!(avg_age = 62.3))
+            .unwrap()
+            .map(|r| r.unwrap().city)
+            .collect();
+        assert_eq!(cities, vec!["Sandy".to_string()]);
+    }
+
+    #[test]
+    fn test_insert_model_serde() {
+        let db = setup();
+        let model = testModel {
+            city: String::from("O'Brien \"Heights\""),
+            population: 12,
+            avg_age: 30.5,
+        };
+        db.insert_model("testModel", &model).unwrap();
+
+        let out: Vec<testModel> = db.select_all("testModel").unwrap();
+        assert_eq!(1, out.len());
+        assert_eq!(out[0].city, model.city);
+    }
+
+    #[test]
+    fn test_insert_model_derived_rows() {
+        #[derive(MigrateTable, FromRow, ToRow, Debug, PartialEq)]
+        struct testRowModel {
+            city: String,
+            population: u64,
+            avg_age: f64,
+        }
+
+        let db = setup();
+        db.create_table(testRowModel::generate_schema()).unwrap();
+        let model = testRowModel {
+            city: String::from("Gresham"),
+            population: 100000,
+            avg_age: 44.3,
+        };
+        db.insert_model("testRowModel", &model).unwrap();
+
+        let out: Vec<testRowModel> = db.select_all("testRowModel").unwrap();
+        assert_eq!(out, vec![model]);
+    }
+
+    #[test]
+    fn test_insert_model_badtable() {
+        let db = setup();
+        let model = testModel {
+            city: String::from("Gresham"),
+            population: 100000,
+            avg_age: 44.3,
+        };
+        assert!(db.insert_model("idontexist", &model).is_err());
+    }
+
+    #[test]
+    fn test_open_error() {
+        let result = DB::new("rsorm_test_missing_dir/rsorm_test");
+        assert!(matches!(result, Err(Error::Sqlite { .. })));
+    }
+
+    #[test]
+    fn test_insert_constraint_violation() {
+        let db = setup();
+        db.conn
+            .execute(
+                "CREATE TABLE testUniqueModel (city String UNIQUE, population u64 NOT NULL,
+                 avg_age f64 CHECK (avg_age > 0))",
+            )
+            .unwrap();
+        db.create_table((
+            String::from("testUniqueModel"),
+            vec![
+                (String::from("city"), String::from("String")),
+                (String::from("population"), String::from("u64")),
+                (String::from("avg_age"), String::from("f64")),
+            ],
+        ))
+        .unwrap();
+        db.insert(
+            "testUniqueModel",
+            sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+        )
+        .unwrap();
+
+        let result = db.insert(
+            "testUniqueModel",
+            sql!(city = "Gresham", population = 10, avg_age = 44.3),
+        );
+        match result {
+            Err(Error::Constraint(v)) => {
+                assert_eq!(v.kind, ConstraintKind::Unique);
+                assert_eq!(v.table, "testUniqueModel");
+                assert_eq!(v.columns, vec!["city"]);
+            }
+            _ => panic!("expected a unique constraint violation"),
+        }
+
+        let result = db.insert("testUniqueModel", sql!(city = "Sandy", avg_age = 44.3));
+        match result {
+            Err(Error::Constraint(v)) => {
+                assert_eq!(v.kind, ConstraintKind::NotNull);
+                assert_eq!(v.columns, vec!["population"]);
+            }
+            _ => panic!("expected a not null constraint violation"),
+        }
+
+        let result = db.insert(
+            "testUniqueModel",
+            sql!(city = "Sandy", population = 10, avg_age = 0),
+        );
+        match result {
+            Err(Error::Constraint(v)) => {
+                assert_eq!(v.kind, ConstraintKind::Check);
+                assert_eq!(v.table, "testUniqueModel");
+            }
+            _ => panic!("expected a check constraint violation"),
+        }
+    }
+
+    #[test]
+    fn test_open_dsn() {
+        let test_db = TestDb::new().unwrap();
+        let path = test_db.path().to_str().unwrap();
+        let dsn = format!("sqlite:{}?mode=rwc&busy_timeout=100", path);
+        let db = DB::open(&dsn).unwrap();
+        assert_eq!(db.dsn(), dsn);
+        drop(db);
+
+        let ro = DB::open(format!("sqlite:{}?mode=ro", path)).unwrap();
+        let result = ro.create_table(testModel::generate_schema());
+        assert!(matches!(result, Err(Error::Sqlite { .. })));
+
+        let result = DB::open(format!("sqlite:{}-missing?mode=rw", path));
+        assert!(matches!(result, Err(Error::Sqlite { .. })));
+        let result = DB::open(format!("sqlite:{}?mode=wat", path));
+        assert!(matches!(result, Err(Error::InvalidDsn(_))));
+
+        let mem = DB::in_memory().unwrap();
+        mem.create_table(testModel::generate_schema()).unwrap();
+        mem.insert(
+            "testModel",
+            sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+        )
+        .unwrap();
+        assert_eq!(1, mem.select_all::<testModel>("testModel").unwrap().len());
+        assert!(!std::path::Path::new(":memory:").exists());
+    }
+
+    #[test]
+    fn test_update() {
+        let db = setup();
+        db.insert(
+            "testModel",
+            sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+        )
+        .unwrap();
+        db.update(
+            "testModel",
+            sql!(population = 100001),
+            sql!(city = "Gresham"),
+        )
+        .unwrap();
+        let out: Vec<testModel> = db.select_all("testModel").unwrap();
+        assert_eq!(out[0].population, 100001);
+
+        let result = db.update("testModel", sql!(bad = 1), sql!(city = "Gresham"));
+        assert!(matches!(result, Err(Error::InvalidColumn { .. })));
+    }
+
+    #[test]
+    fn test_transaction_commit() {
+        let db = setup();
+        let count = db
+            .transaction(|tx| {
+                tx.insert(
+                    "testModel",
+                    sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+                )?;
+                tx.insert(
+                    "testModel",
+                    sql!(city = "Sandy", population = 10000, avg_age = 62.3),
+                )?;
+                Ok::<_, Error>(tx.select_all::<testModel>("testModel")?.len())
+            })
+            .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(2, db.select_all::<testModel>("testModel").unwrap().len());
+    }
+
+    #[test]
+    fn test_transaction_rollback() {
+        let db = setup();
+        let result = db.transaction(|tx| {
+            tx.insert(
+                "testModel",
+                sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+            )?;
+            tx.insert("idontexist", sql!(city = "Sandy"))
+        });
+        assert!(matches!(result, Err(Error::UnknownTable(_))));
+        assert_eq!(0, db.select_all::<testModel>("testModel").unwrap().len());
+
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            db.transaction(|tx| -> Result<()> {
+                tx.insert(
+                    "testModel",
+                    sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+                )?;
+                panic!("interrupted mid-transaction");
+            })
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(0, db.select_all::<testModel>("testModel").unwrap().len());
+    }
+
+    #[test]
+    fn test_transaction_guard() {
+        let db = setup();
+        {
+            let tx = db.begin().unwrap();
+            tx.insert(
+                "testModel",
+                sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+            )
+            .unwrap();
+        }
+        assert_eq!(0, db.select_all::<testModel>("testModel").unwrap().len());
+
+        let tx = db.begin().unwrap();
+        tx.insert(
+            "testModel",
+            sql!(city = "Sandy", population = 10000, avg_age = 62.3),
+        )
+        .unwrap();
+        tx.commit().unwrap();
+        assert_eq!(1, db.select_all::<testModel>("testModel").unwrap().len());
+    }
+
+    #[test]
+    fn test_transaction_nested() {
+        let db = setup();
+        db.transaction(|tx| {
+            tx.insert(
+                "testModel",
+                sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+            )?;
+            let inner = tx.transaction(|inner| {
+                assert!(inner.is_nested());
+                inner.insert(
+                    "testModel",
+                    sql!(city = "Sandy", population = 10000, avg_age = 62.3),
+                )?;
+                inner.delete("testModel", sql!(bad = 1))
+            });
+            assert!(inner.is_err());
+            tx.delete("testModel", sql!(city = "Nowhere"))
+        })
+        .unwrap();
+
+        let out: Vec<testModel> = db.select_all("testModel").unwrap();
+        assert_eq!(1, out.len());
+        assert_eq!(out[0].city, "Gresham");
+    }
+
+    #[test]
+    fn test_transaction_out_of_order() {
+        let db = setup();
+        let outer = db.begin().unwrap();
+        outer
+            .insert(
+                "testModel",
+                sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+            )
+            .unwrap();
+        let inner = db.begin().unwrap();
+        match outer.commit() {
+            Err(Error::TransactionOrder(1, 2)) => {}
+            other => panic!("expected TransactionOrder, got {:?}", other),
+        }
+        match inner.commit() {
+            Err(Error::TransactionOrder(2, 0)) => {}
+            other => panic!("expected TransactionOrder, got {:?}", other),
+        }
+        assert!(!db.begin().unwrap().is_nested());
+        assert_eq!(0, db.select_all::<testModel>("testModel").unwrap().len());
+    }
+
+    #[test]
+    fn test_insert_many() {
+        let db = setup();
+        let models: Vec<testModel> = (0..100)
+            .map(|i| testModel {
+                city: format!("City {}", i),
+                population: i,
+                avg_age: 40.5,
+            })
+            .collect();
+        assert_eq!(100, db.insert_many("testModel", &models).unwrap());
+        assert_eq!(0, db.insert_many::<testModel>("testModel", &[]).unwrap());
+
+        let out: Vec<testModel> = db.select_where("testModel", sql!(population = 42)).unwrap();
+        assert_eq!(out[0].city, "City 42");
+        assert_eq!(100, db.select_all::<testModel>("testModel").unwrap().len());
+
+        let result = db.insert_many("idontexist", &models);
+        assert!(matches!(result, Err(Error::UnknownTable(_))));
+    }
+
+    #[test]
+    fn test_insert_many_failures() {
+        let db = setup();
+        db.conn
+            .execute(
+                "CREATE TABLE testUniqueModel (city String UNIQUE, population u64, avg_age f64)",
+            )
+            .unwrap();
+        db.create_table((
+            String::from("testUniqueModel"),
+            vec![
+                (String::from("city"), String::from("String")),
+                (String::from("population"), String::from("u64")),
+                (String::from("avg_age"), String::from("f64")),
+            ],
+        ))
+        .unwrap();
+
+        let models: Vec<testModel> = ["Gresham", "Gresham", "Sandy", "Sandy"]
+            .iter()
+            .map(|city| testModel {
+                city: city.to_string(),
+                population: 10,
+                avg_age: 40.5,
+            })
+            .collect();
+        match db.insert_many("testUniqueModel", &models) {
+            Err(Error::Batch(failures)) => {
+                let rows: Vec<usize> = failures.iter().map(|f| f.row).collect();
+                assert_eq!(rows, vec![1, 3]);
+                assert!(matches!(failures[0].error, Error::Constraint(_)));
+            }
+            _ => panic!("expected the batch to fail"),
+        }
+        let out: Vec<testModel> = db.select_all("testUniqueModel").unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_insert_values() {
+        let db = setup();
+        let inserted = db
+            .insert_values(
+                "testModel",
+                vec![
+                    sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+                    sql!(city = "Sandy", population = 10000, avg_age = 62.3),
+                ],
+            )
+            .unwrap();
+        assert_eq!(inserted, 2);
+        let out: Vec<testModel> = db.select_where("testModel", sql!(city = "Sandy")).unwrap();
+        assert_eq!(out[0].population, 10000);
+
+        let result = db.insert_values(
+            "testModel",
+            vec![
+                sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+                sql!(city = "Sandy"),
+            ],
+        );
+        match result {
+            Err(Error::Batch(failures)) => assert_eq!(failures[0].row, 1),
+            _ => panic!("expected the batch to fail"),
+        }
+        assert_eq!(2, db.select_all::<testModel>("testModel").unwrap().len());
+    }
+
+    #[test]
+    fn test_statement_cache() {
+        let db = setup();
+        db.insert(
+            "testModel",
+            sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+        )
+        .unwrap();
+        assert_eq!(db.statement_cache_capacity(), 32);
+
+        for _ in 0..3 {
+            let out: Vec<testModel> = db
+                .select_where("testModel", sql!(city = "Gresham"))
+                .unwrap();
+            assert_eq!(out.len(), 1);
+        }
+        let out: Vec<testModel> = db.select_where("testModel", sql!(city = "Sandy")).unwrap();
+        assert!(out.is_empty());
+        // the insert is cached too, and both selects share one statement,
+        // only the bound value differs
+        assert_eq!(db.conn.statements().len(), 2);
+
+        // a statement still in use is prepared again rather than shared
+        let mut outer = db.iter_all::<testModel>("testModel").unwrap();
+        let inner: Vec<testModel> = db.select_all("testModel").unwrap();
+        assert_eq!(inner.len(), 1);
+        assert!(outer.next().unwrap().is_ok());
+        drop(outer);
+        assert_eq!(db.conn.statements().len(), 3);
+
+        db.set_statement_cache_capacity(1);
+        assert_eq!(db.conn.statements().len(), 1);
+        db.set_statement_cache_capacity(0);
+        db.select_all::<testModel>("testModel").unwrap();
+        assert_eq!(db.conn.statements().len(), 0);
+    }
+
+    #[test]
+    fn test_pool_threads() {
+        let test_db = TestDb::new().unwrap();
+        let pool = DbPool::builder(test_db.path())
+            .max_size(4)
+            .pragma("busy_timeout", "5000")
+            .build()
+            .unwrap();
+        pool.get()
+            .unwrap()
+            .create_table(testModel::generate_schema())
+            .unwrap();
+
+        let workers: Vec<_> = (0..8)
+            .map(|i| {
+                let pool = pool.clone();
+                std::thread::spawn(move || {
+                    let db = pool.get().unwrap();
+                    db.insert_model(
+                        "testModel",
+                        &testModel {
+                            city: format!("City {}", i),
+                            population: i,
+                            avg_age: 40.5,
+                        },
+                    )
+                    .unwrap();
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        // the table registered through the first connection is known to every other one
+        assert!(pool.open_connections() <= 4);
+        let dbs: Vec<PooledDb> = (0..pool.open_connections())
+            .map(|_| pool.get().unwrap())
+            .collect();
+        for db in dbs.iter() {
+            assert_eq!(8, db.select_all::<testModel>("testModel").unwrap().len());
+        }
+        drop(dbs);
+        assert_eq!(pool.idle_connections(), pool.open_connections());
+    }
+
+    #[test]
+    fn test_pool_timeout() {
+        let test_db = TestDb::new().unwrap();
+        let pool = DbPool::builder(test_db.path())
+            .max_size(1)
+            .checkout_timeout(std::time::Duration::from_millis(20))
+            .build()
+            .unwrap();
+        let held = pool.get().unwrap();
+        assert!(matches!(pool.get(), Err(Error::PoolTimeout(_))));
+        drop(held);
+        assert!(pool.get().is_ok());
+
+        let missing = DbPool::new("rsorm_test_missing_dir/rsorm_test");
+        assert!(matches!(missing, Err(Error::Sqlite { .. })));
+    }
+
+    #[test]
+    fn test_pool_reset() {
+        let test_db = TestDb::new().unwrap();
+        let pool = DbPool::builder(test_db.path()).max_size(1).build().unwrap();
+        {
+            let db = pool.get().unwrap();
+            db.create_table(testModel::generate_schema()).unwrap();
+            db.set_retry_policy(Some(RetryPolicy::new(3)));
+            db.set_statement_cache_capacity(2);
+            db.execute("BEGIN", &[]).unwrap();
+            db.insert(
+                "testModel",
+                sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+            )
+            .unwrap();
+        }
+
+        let db = pool.get().unwrap();
+        assert!(db.is_autocommit());
+        assert!(db.retry_policy().is_none());
+        assert_eq!(db.statement_cache_capacity(), 32);
+        assert_eq!(0, db.select_all::<testModel>("testModel").unwrap().len());
+        std::mem::forget(db.begin().unwrap());
+        drop(db);
+
+        let db = pool.get().unwrap();
+        assert!(!db.begin().unwrap().is_nested());
+        assert_eq!(pool.open_connections(), 1);
+    }
+
+    #[test]
+    fn test_open_config() {
+        fn pragma(db: &DB, name: &str) -> String {
+            let mut stmt = db.conn.prepare(format!("PRAGMA {}", name)).unwrap();
+            stmt.next().unwrap();
+            stmt.read::<String>(0).unwrap()
+        }
+
+        let config = DbConfig::new()
+            .read_write_create()
+            .journal_mode(JournalMode::Wal)
+            .synchronous(Synchronous::Normal)
+            .foreign_keys(true)
+            .busy_timeout(std::time::Duration::from_secs(1))
+            .cache_size(-4000)
+            .temp_store(TempStore::Memory)
+            .statement_cache_capacity(8);
+        let test_db = TestDb::new().unwrap();
+        let db = DB::open_with(test_db.path(), &config).unwrap();
+        assert_eq!(pragma(&db, "journal_mode"), "wal");
+        assert_eq!(pragma(&db, "synchronous"), "1");
+        assert_eq!(pragma(&db, "foreign_keys"), "1");
+        assert_eq!(pragma(&db, "busy_timeout"), "1000");
+        assert_eq!(pragma(&db, "cache_size"), "-4000");
+        assert_eq!(pragma(&db, "temp_store"), "2");
+        assert_eq!(db.statement_cache_capacity(), 8);
+        db.create_table(testModel::generate_schema()).unwrap();
+
+        let ro = DB::open_with(test_db.path(), &DbConfig::new().read_only()).unwrap();
+        ro.create_table(testModel::generate_schema()).unwrap();
+        assert!(ro.select_all::<testModel>("testModel").unwrap().is_empty());
+        let result = ro.insert(
+            "testModel",
+            sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+        );
+        assert!(matches!(result, Err(Error::Sqlite { .. })));
+        drop(ro);
+        drop(db);
+
+        let db = DB::open_with(":memory:", &config).unwrap();
+        assert_eq!(pragma(&db, "journal_mode"), "memory");
+    }
+
+    #[test]
+    fn test_test_db() {
+        let path = {
+            let db = TestDb::with_config(&DbConfig::new().journal_mode(JournalMode::Wal)).unwrap();
+            let other = TestDb::new().unwrap();
+            assert_ne!(db.path(), other.path());
+            assert!(db.path().exists());
+            db.path().to_path_buf()
+        };
+        assert!(!path.exists());
+        assert!(!Path::new(&format!("{}-wal", path.display())).exists());
+    }
+
+    #[test]
+    fn test_busy_retries() {
+        let test_db = setup();
+        let fast = RetryPolicy::new(3).backoff(
+            std::time::Duration::from_millis(1),
+            std::time::Duration::from_millis(1),
+        );
+        let other = DB::open_with(test_db.path(), &DbConfig::new().retry(fast)).unwrap();
+        other.create_table(testModel::generate_schema()).unwrap();
+
+        test_db.conn.execute("BEGIN IMMEDIATE").unwrap();
+        let result = other.insert(
+            "testModel",
+            sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+        );
+        match result {
+            Err(Error::RetriesExhausted { attempts, error }) => {
+                assert_eq!(attempts, 3);
+                assert!(error.is_busy());
+            }
+            _ => panic!("expected the retries to be exhausted"),
+        }
+        let result = other.transaction(|tx| tx.delete("testModel", sql!(city = "Gresham")));
+        assert!(matches!(result, Err(Error::RetriesExhausted { .. })));
+
+        // the lock is released by another thread while the write is being retried
+        other.set_retry_policy(Some(RetryPolicy::new(200).backoff(
+            std::time::Duration::from_millis(1),
+            std::time::Duration::from_millis(5),
+        )));
+        let holder = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(30));
+            test_db.conn.execute("COMMIT").unwrap();
+            test_db
+        });
+        other
+            .transaction(|tx| {
+                tx.insert(
+                    "testModel",
+                    sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+                )
+            })
+            .unwrap();
+        let test_db = holder.join().unwrap();
+        assert_eq!(
+            1,
+            test_db.select_all::<testModel>("testModel").unwrap().len()
+        );
+    }
+
+    #[test]
+    fn test_create_table_while_locked() {
+        #[derive(MigrateTable)]
+        struct testLocked {
+            name: String,
+        }
+
+        let test_db = setup();
+        let retry = RetryPolicy::new(200).backoff(
+            std::time::Duration::from_millis(1),
+            std::time::Duration::from_millis(5),
+        );
+        let pool = DbPool::builder(test_db.path())
+            .config(DbConfig::new().retry(retry))
+            .build()
+            .unwrap();
+        let reader = pool.get().unwrap();
+        reader.create_table(testModel::generate_schema()).unwrap();
+
+        // the table can't be created until the lock is released
+        test_db.conn.execute("BEGIN IMMEDIATE").unwrap();
+        let created = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let creator = {
+            let pool = pool.clone();
+            let created = created.clone();
+            std::thread::spawn(move || {
+                let db = pool.get().unwrap();
+                let result = db.create_table(testLocked::generate_schema());
+                created.store(true, std::sync::atomic::Ordering::SeqCst);
+                result
+            })
+        };
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        // other connections can still check their queries against the registered tables
+        assert_eq!(
+            0,
+            reader.select_all::<testModel>("testModel").unwrap().len()
+        );
+        assert!(!created.load(std::sync::atomic::Ordering::SeqCst));
+
+        test_db.conn.execute("COMMIT").unwrap();
+        creator.join().unwrap().unwrap();
+        assert!(reader.check_table("testLocked").is_ok());
+    }
+
+    #[test]
+    fn test_split_db() {
+        let test_db = TestDb::new().unwrap();
+        let split = SplitDb::builder(test_db.path()).readers(2).build().unwrap();
+        split.create_table(testModel::generate_schema()).unwrap();
+        split
+            .insert(
+                "testModel",
+                sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+            )
+            .unwrap();
+        let out: Vec<testModel> = split
+            .select_where("testModel", sql!(city = "Gresham"))
+            .unwrap();
+        assert_eq!(out.len(), 1);
+
+        // readers keep reading the last commit while the writer is mid-transaction
+        let writer = split.writer().unwrap();
+        let tx = writer.begin().unwrap();
+        tx.insert(
+            "testModel",
+            sql!(city = "Sandy", population = 10000, avg_age = 62.3),
+        )
+        .unwrap();
+        assert_eq!(2, tx.select_all::<testModel>("testModel").unwrap().len());
+        assert_eq!(1, split.select_all::<testModel>("testModel").unwrap().len());
+        tx.commit().unwrap();
+        drop(writer);
+        assert_eq!(2, split.select_all::<testModel>("testModel").unwrap().len());
+
+        // other reads run on a reader too
+        let cities: Vec<String> = split
+            .read(|db| {
+                db.iter_where::<testModel>("testModel", sql!(city = "Sandy"))?
+                    .map(|row| row.map(|m| m.city))
+                    .collect()
+            })
+            .unwrap();
+        assert_eq!(cities, vec!["Sandy"]);
+        let out = split
+            .read(|db| {
+                db.query::<testModel>()
+                    .filter(sql!(city = "Gresham"))
+                    .fetch()
+            })
+            .unwrap();
+        assert_eq!(out[0].population, 100000);
+        assert_eq!(split.count::<testModel>(sql!(city = "Sandy")).unwrap(), 1);
+        assert!(split.exists("testModel", sql!(city = "Sandy")).unwrap());
+        let found: Option<testModel> = split.find_one("testModel", sql!(city = "Sandy")).unwrap();
+        assert_eq!(found.unwrap().population, 10000);
+        let found: testModel = split.get("testModel", sql!(city = "Gresham")).unwrap();
+        assert_eq!(found.population, 100000);
+        let result = split.query_as::<Row>("DELETE FROM testModel", &[]);
+        assert!(matches!(result, Err(Error::Sqlite { .. })));
+        assert_eq!(
+            split
+                .execute("DELETE FROM testModel WHERE city = ?", &[&"Sandy"])
+                .unwrap(),
+            1
+        );
+        let rows = split
+            .select_rows("testModel", testModel::POPULATION.gt(50000))
+            .unwrap();
+        assert_eq!(rows[0].get::<String>("city").unwrap(), "Gresham");
+        let result = split.read(|db| db.delete("testModel", sql!(city = "Sandy")));
+        assert!(matches!(result, Err(Error::Sqlite { .. })));
+
+        let result = split
+            .reader()
+            .unwrap()
+            .delete("testModel", sql!(city = "Sandy"));
+        assert!(matches!(result, Err(Error::Sqlite { .. })));
+
+        assert!(matches!(
+            SplitDb::open(":memory:"),
+            Err(Error::InvalidDsn(_))
+        ));
+    }
+
+    #[test]
+    fn test_query_builder() {
+        let db = setup();
+        let models: Vec<testModel> = (0..10)
+            .map(|i| testModel {
+                city: format!("City {}", i),
+                population: i * 1000,
+                avg_age: 30.0 + i as f64,
+            })
+            .collect();
+        db.insert_many("testModel", &models).unwrap();
+
+        let query = db
+            .query::<testModel>()
+            .filter(Condition::ge("population", 2000).and(Condition::like("city", "City %")))
+            .filter(!Condition::in_("population", vec![5000, 6000]))
+            .order_by_desc("avg_age")
+            .limit(3)
+            .offset(1);
+        assert_eq!(
+            query.to_sql(),
+            "SELECT * FROM testModel WHERE ((population >= ? AND city LIKE ?) AND NOT (population IN (?, ?))) \
+             ORDER BY avg_age DESC LIMIT ? OFFSET ?"
+        );
+        assert_eq!(query.params()[0], Value::Integer(2000));
+        let out = query.fetch().unwrap();
+        let cities: Vec<&str> = out.iter().map(|m| m.city.as_str()).collect();
+        assert_eq!(cities, vec!["City 8", "City 7", "City 4"]);
+
+        let out = db
+            .query::<testModel>()
+            .filter(sql!(city = "City 3"))
+            .fetch()
+            .unwrap();
+        assert_eq!(out[0].population, 3000);
+
+        let out: Vec<testModel> = db
+            .query_table("testModel")
+            .filter(Condition::is_null("city").or(Condition::lt("population", 1000)))
+            .order_by("city")
+            .fetch()
+            .unwrap();
+        assert_eq!(out.len(), 1);
+
+        // values sqlite can't store are rejected rather than wrapped
+        let out = db
+            .query::<testModel>()
+            .filter(Condition::gt("population", u64::MAX - 1))
+            .fetch();
+        assert!(matches!(
+            out,
+            Err(Error::UnsupportedType { ref column, .. }) if column == "population"
+        ));
+        let out = db.query::<testModel>().limit(u64::MAX).fetch().unwrap();
+        assert_eq!(out.len(), 10);
+
+        let result = db.query::<testModel>().order_by("bad").fetch();
+        assert!(matches!(result, Err(Error::InvalidColumn { .. })));
+        let result = db.query_table::<testModel>("idontexist").fetch();
+        assert!(matches!(result, Err(Error::UnknownTable(_))));
+    }
+
+    #[test]
+    fn test_typed_columns() {
+        let db = setup();
+        let models: Vec<testModel> = (0..5)
+            .map(|i| testModel {
+                city: format!("City {}", i),
+                population: i * 1000,
+                avg_age: 30.0 + i as f64,
+            })
+            .collect();
+        db.insert_many("testModel", &models).unwrap();
+
+        assert_eq!(testModel::POPULATION.name(), "population");
+        assert_eq!(
+            testModel::POPULATION.gt(2000),
+            Condition::gt("population", 2000u64)
+        );
+
+        let out = db
+            .query::<testModel>()
+            .filter(
+                testModel::POPULATION
+                    .ge(1000)
+                    .and(testModel::CITY.like("City %")),
+            )
+            .filter(!testModel::AVG_AGE.in_(vec![32.0, 33.0]))
+            .order_by_desc(testModel::POPULATION)
+            .fetch()
+            .unwrap();
+        let cities: Vec<&str> = out.iter().map(|m| m.city.as_str()).collect();
+        assert_eq!(cities, vec!["City 4", "City 1"]);
+
+        let out = db
+            .query::<testModel>()
+            .filter(testModel::CITY.eq("City 3").or(testModel::CITY.is_null()))
+            .fetch()
+            .unwrap();
+        assert_eq!(out.len(), 1);
+
+        // comparing with None tests for null
+        db.insert(
+            "testModel",
+            sql!(city = "Nowhere", population = null, avg_age = 1.5),
+        )
+        .unwrap();
+        #[derive(Deserialize)]
+        struct City {
+            city: String,
+        }
+        let maybe: Column<testModel, Option<u64>> = Column::new("population");
+        let cities = |condition: Condition| -> Vec<String> {
+            db.query_table::<City>("testModel")
+                .filter(condition)
+                .order_by(testModel::CITY)
+                .fetch()
+                .unwrap()
+                .into_iter()
+                .map(|c| c.city)
+                .collect()
+        };
+        assert_eq!(cities(maybe.eq(None)), vec!["Nowhere"]);
+        assert_eq!(cities(maybe.ne(None)).len(), 5);
+        assert_eq!(cities(maybe.eq(1000)), vec!["City 1"]);
+        assert_eq!(cities(maybe.ne(1000)).len(), 4);
+    }
+
+    #[test]
+    fn test_cond_macro() {
+        let db = setup();
+        let models: Vec<testModel> = (0..5)
+            .map(|i| testModel {
+                city: format!("City {}", i),
+                population: i * 1000,
+                avg_age: 30.0 + i as f64,
+            })
+            .collect();
+        db.insert_many("testModel", &models).unwrap();
+
+        let name = "City 1";
+        let condition = cond!(testModel, population > 2000 || city == name);
+        assert_eq!(
+            condition,
+            Condition::gt("population", 2000u64).or(Condition::eq("city", "City 1"))
+        );
+
+        let out = db
+            .query::<testModel>()
+            .filter(condition)
+            .order_by(testModel::CITY)
+            .fetch()
+            .unwrap();
+        let cities: Vec<&str> = out.iter().map(|m| m.city.as_str()).collect();
+        assert_eq!(cities, vec!["City 1", "City 3", "City 4"]);
+
+        let min_age = 31.5;
+        let out = db
+            .query::<testModel>()
+            .filter(cond!(
+                testModel,
+                !(avg_age < min_age) && city.like("City %") && !population.in_(vec![3000])
+            ))
+            .fetch()
+            .unwrap();
+        let cities: Vec<&str> = out.iter().map(|m| m.city.as_str()).collect();
+        assert_eq!(cities, vec!["City 2", "City 4"]);
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let db = setup();
+        let models: Vec<testModel> = (0..6)
+            .map(|i| testModel {
+                city: format!("City {}", i % 3),
+                population: i * 1000,
+                avg_age: 30.0 + i as f64,
+            })
+            .collect();
+        db.insert_many("testModel", &models).unwrap();
+
+        assert_eq!(db.count::<testModel>(sql!(city = "City 1")).unwrap(), 2);
+        let query = || {
+            db.query::<testModel>()
+                .filter(testModel::POPULATION.ge(1000))
+        };
+        assert_eq!(query().count().unwrap(), 5);
+        assert_eq!(query().sum(testModel::POPULATION).unwrap(), Some(15000));
+        assert_eq!(query().avg(testModel::AVG_AGE).unwrap(), Some(33.0));
+        assert_eq!(
+            query().min(testModel::CITY).unwrap(),
+            Some(String::from("City 0"))
+        );
+        assert_eq!(query().max(testModel::POPULATION).unwrap(), Some(5000));
+        let none = db
+            .query::<testModel>()
+            .filter(testModel::POPULATION.gt(9000));
+        assert_eq!(none.sum(testModel::POPULATION).unwrap(), None);
+
+        let groups = db
+            .query::<testModel>()
+            .order_by(testModel::CITY)
+            .group_by(testModel::CITY)
+            .count()
+            .unwrap();
+        assert_eq!(
+            groups,
+            vec![
+                (String::from("City 0"), 2),
+                (String::from("City 1"), 2),
+                (String::from("City 2"), 2),
+            ]
+        );
+
+        let groups = db
+            .query::<testModel>()
+            .order_by_desc(testModel::CITY)
+            .group_by(testModel::CITY)
+            .having(Aggregate::sum(testModel::POPULATION).ge(5000))
+            .avg(testModel::AVG_AGE)
+            .unwrap();
+        assert_eq!(
+            groups,
+            vec![
+                (String::from("City 2"), Some(33.5)),
+                (String::from("City 1"), Some(32.5)),
+            ]
+        );
+
+        let result = db
+            .query::<testModel>()
+            .group_by(testModel::CITY)
+            .aggregate::<i64>(Aggregate::count_of("bad"));
+        assert!(matches!(result, Err(Error::InvalidColumn { .. })));
+    }
+
+    #[test]
+    fn test_joins() {
+        #[derive(MigrateTable, Deserialize, Serialize, Debug, PartialEq)]
+        struct testMayor {
+            name: String,
+            city: String,
+        }
+
+        impl Related<testMayor> for testModel {
+            fn join_columns() -> (&'static str, &'static str) {
+                ("city", "city")
+            }
+        }
+
+        // a table with a column the model doesn't have, which a join must not select
+        let db = TestDb::new().unwrap();
+        db.conn
+            .execute(
+                "CREATE TABLE testModel (id INTEGER PRIMARY KEY, city TEXT, population INTEGER, \
+                 avg_age REAL)",
+            )
+            .unwrap();
+        db.create_table(testModel::generate_schema()).unwrap();
+        db.create_table(testMayor::generate_schema()).unwrap();
+        let models: Vec<testModel> = (0..3)
+            .map(|i| testModel {
+                city: format!("City {}", i),
+                population: i * 1000,
+                avg_age: 30.0 + i as f64,
+            })
+            .collect();
+        db.insert_many("testModel", &models).unwrap();
+        let mayors = vec![
+            testMayor {
+                name: String::from("Ada"),
+                city: String::from("City 0"),
+            },
+            testMayor {
+                name: String::from("Grace"),
+                city: String::from("City 2"),
+            },
+        ];
+        db.insert_many("testMayor", &mayors).unwrap();
+
+        let query = db
+            .query::<testModel>()
+            .join(testModel::CITY, testMayor::CITY)
+            .filter(testModel::POPULATION.ge(0))
+            .filter_joined(testMayor::NAME.like("%a%"))
+            .order_by_desc(testModel::POPULATION);
+        assert_eq!(
+            query.to_sql(),
+            "SELECT l.city, l.population, l.avg_age, r.name, r.city FROM testModel AS l \
+             INNER JOIN testMayor AS r ON l.city = r.city \
+             WHERE (l.population >= ? AND r.name LIKE ?) ORDER BY l.population DESC"
+        );
+        let out = query.fetch().unwrap();
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].0.city, "City 2");
+        assert_eq!(out[0].1, mayors[1]);
+        assert_eq!(out[1].1, mayors[0]);
+
+        let out = db
+            .query::<testModel>()
+            .left_join_related::<testMayor>()
+            .order_by(testModel::CITY)
+            .fetch()
+            .unwrap();
+        let names: Vec<Option<&str>> = out
+            .iter()
+            .map(|(_, mayor)| mayor.as_ref().map(|m| m.name.as_str()))
+            .collect();
+        assert_eq!(names, vec![Some("Ada"), None, Some("Grace")]);
+
+        let result = db
+            .query::<testModel>()
+            .join_related::<testMayor>()
+            .filter_joined(Condition::eq("population", 1))
+            .fetch();
+        assert!(matches!(result, Err(Error::InvalidColumn { .. })));
+    }
+
+    #[test]
+    fn test_projection() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Summary {
+            population: u64,
+            city: String,
+        }
+
+        let db = setup();
+        let models: Vec<testModel> = (0..3)
+            .map(|i| testModel {
+                city: format!("City {}", i),
+                population: i * 1000,
+                avg_age: 30.0 + i as f64,
+            })
+            .collect();
+        db.insert_many("testModel", &models).unwrap();
+
+        let query = db
+            .query::<testModel>()
+            .filter(testModel::POPULATION.gt(0))
+            .order_by(testModel::CITY)
+            .select((testModel::CITY, testModel::POPULATION));
+        assert_eq!(
+            query.to_sql(),
+            "SELECT city, population FROM testModel WHERE population > ? ORDER BY city ASC"
+        );
+        let out: Vec<(String, u64)> = query.fetch().unwrap();
+        assert_eq!(
+            out,
+            vec![
+                (String::from("City 1"), 1000),
+                (String::from("City 2"), 2000)
+            ]
+        );
+
+        let ages: Vec<f64> = db
+            .query::<testModel>()
+            .order_by_desc(testModel::AVG_AGE)
+            .select(testModel::AVG_AGE)
+            .fetch()
+            .unwrap();
+        assert_eq!(ages, vec![32.0, 31.0, 30.0]);
+
+        let out: Vec<Summary> = db
+            .query::<testModel>()
+            .filter(sql!(city = "City 2"))
+            .select_into(&["city", "population"])
+            .fetch()
+            .unwrap();
+        assert_eq!(
+            out,
+            vec![Summary {
+                population: 2000,
+                city: String::from("City 2"),
+            }]
+        );
+
+        let result = db
+            .query::<testModel>()
+            .select_into::<Summary, _>(&["city", "people"])
+            .fetch();
+        assert!(matches!(result, Err(Error::InvalidColumn { .. })));
+    }
+
+    #[test]
+    fn test_select_rows() {
+        let db = setup();
+        db.insert(
+            "testModel",
+            sql!(city = "Gresham", population = 100000, avg_age = 44.3),
+        )
+        .unwrap();
+        db.insert(
+            "testModel",
+            sql!(city = null, population = 5, avg_age = 1.5),
+        )
+        .unwrap();
+
+        let rows = db
+            .select_rows("testModel", sql!(population = 100000))
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        let row = &rows[0];
+        assert_eq!(row.columns(), ["city", "population", "avg_age"]);
+        assert_eq!(row.get::<String>("city").unwrap(), "Gresham");
+        assert_eq!(row.get::<u64>("population").unwrap(), 100000);
+        assert_eq!(row.value("avg_age"), Some(&Value::Real(44.3)));
+        assert!(matches!(row.get::<u64>("city"), Err(Error::Decode(_))));
+        assert!(matches!(row.get::<u64>("mayor"), Err(Error::Decode(_))));
+
+        let rows: Vec<Row> = db
+            .query_table("testModel")
+            .filter(Condition::is_null("city"))
+            .fetch()
+            .unwrap();
+        assert_eq!(rows[0].value("city"), Some(&Value::Null));
+        assert_eq!(rows[0].get::<Option<String>>("city").unwrap(), None);
+        let pairs: Vec<(&str, &Value)> = rows[0].iter().collect();
+        assert_eq!(pairs[1], ("population", &Value::Integer(5)));
+
+        // rows can be written back as they were read
+        db.insert_model("testModel", &rows[0]).unwrap();
+        assert_eq!(db.count::<testModel>(sql!(population = 5)).unwrap(), 2);
+
+        let result = db.select_rows("idontexist", sql!(population = 5));
+        assert!(matches!(result, Err(Error::UnknownTable(_))));
+    }
+
+    #[test]
+    fn test_single_record_lookups() {
+        let db = setup();
+        let models: Vec<testModel> = (0..3)
+            .map(|i| testModel {
+                city: format!("City {}", i),
+                population: i % 2,
+                avg_age: 30.0 + i as f64,
+            })
+            .collect();
+        db.insert_many("testModel", &models).unwrap();
+
+        let found: Option<testModel> = db.find_one("testModel", sql!(city = "City 1")).unwrap();
+        assert_eq!(found.unwrap().avg_age, 31.0);
+        let found: Option<testModel> = db.find_one("testModel", sql!(city = "City 9")).unwrap();
+        assert!(found.is_none());
+        let result = db.find_one::<testModel>("testModel", sql!(population = 0));
+        assert!(matches!(result, Err(Error::TooManyRows(_))));
+
+        let model: testModel = db.get("testModel", sql!(population = 1)).unwrap();
+        assert_eq!(model.city, "City 1");
+        let result = db.get::<testModel>("testModel", sql!(city = "City 9"));
+        assert!(matches!(result, Err(Error::NotFound(_))));
+        let result = db.get::<testModel>("testModel", sql!(population = 0));
+        assert!(matches!(result, Err(Error::TooManyRows(_))));
+
+        assert!(db.exists("testModel", sql!(population = 0)).unwrap());
+        assert!(!db.exists("testModel", sql!(population = 5)).unwrap());
+        let result = db.exists("testModel", sql!(people = 5));
+        assert!(matches!(result, Err(Error::InvalidColumn { .. })));
+
+        let first = db
+            .query::<testModel>()
+            .filter(testModel::POPULATION.eq(0))
+            .order_by_desc(testModel::AVG_AGE)
+            .first()
+            .unwrap();
+        assert_eq!(first.unwrap().city, "City 2");
+        let only = db
+            .query::<testModel>()
+            .filter(testModel::POPULATION.eq(0))
+            .limit(1)
+            .get()
+            .unwrap();
+        assert_eq!(only.city, "City 0");
+    }
+
+    #[test]
+    fn test_raw_sql() {
+        let db = setup();
+        let changed = db
+            .execute(
+                "INSERT INTO testModel (city, population, avg_age) VALUES (?, ?, ?), (?, ?, ?)",
+                &[
+                    &"Gresham",
+                    &100000u64,
+                    &44.3,
+                    &"Portland",
+                    &650000u64,
+                    &38.2,
+                ],
+            )
+            .unwrap();
+        assert_eq!(changed, 2);
+
+        let out: Vec<testModel> = db
+            .query_as(
+                "SELECT * FROM testModel WHERE population > ? AND city LIKE ?",
+                &[&200000u64, &"P%"],
+            )
+            .unwrap();
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].city, "Portland");
+
+        let out: Vec<(String, f64)> = db
+            .query_as(
+                "SELECT upper(city), avg_age * 2 FROM testModel ORDER BY city",
+                &[],
+            )
+            .unwrap();
+        assert_eq!(out[0], (String::from("GRESHAM"), 88.6));
+
+        let rows: Vec<Row> = db
+            .query_as("SELECT count(*) AS n FROM testModel", &[])
+            .unwrap();
+        assert_eq!(rows[0].get::<i64>("n").unwrap(), 2);
+
+        let changed = db
+            .execute("DELETE FROM testModel WHERE city = ?", &[&"Gresham"])
+            .unwrap();
+        assert_eq!(changed, 1);
+        assert_eq!(
+            db.execute("CREATE INDEX city_idx ON testModel (city)", &[])
+                .unwrap(),
+            0
+        );
+
+        let result = db.execute("DELETE FROM nowhere", &[]);
+        assert!(matches!(result, Err(Error::Sqlite { .. })));
+
+        // values sqlite can't store are rejected, naming the parameter
+        let result = db.query_as::<Row>("SELECT ?, ?", &[&1u64, &u64::MAX]);
+        assert!(matches!(
+            result,
+            Err(Error::UnsupportedType { ref column, .. }) if column == "parameter 2"
+        ));
+    }
+
+    #[test]
+    fn test_recursive_queries() {
+        #[derive(MigrateTable, Deserialize, Serialize)]
+        struct testCategory {
+            id: u64,
+            parent: u64,
+            name: String,
+        }
+
+        impl Related<testCategory> for testCategory {
+            fn join_columns() -> (&'static str, &'static str) {
+                ("parent", "id")
+            }
+        }
+
+        let db = TestDb::new().unwrap();
+        db.create_table(testCategory::generate_schema()).unwrap();
+        // 1 -> 2 -> 4 -> 5, and 1 -> 3
+        let categories: Vec<testCategory> = [(1, 0), (2, 1), (3, 1), (4, 2), (5, 4), (6, 0)]
+            .iter()
+            .map(|&(id, parent)| testCategory {
+                id,
+                parent,
+                name: format!("Category {}", id),
+            })
+            .collect();
+        db.insert_many("testCategory", &categories).unwrap();
+
+        let ids = |out: Vec<testCategory>| out.iter().map(|c| c.id).collect::<Vec<_>>();
+        let query = db
+            .query::<testCategory>()
+            .descendants(testCategory::ID.eq(2))
+            .order_by(testCategory::ID);
+        assert_eq!(
+            query.to_sql(),
+            "WITH RECURSIVE rsorm_tree AS (SELECT * FROM testCategory WHERE parent IN \
+             (SELECT id FROM testCategory WHERE id = ?) UNION SELECT n.* FROM testCategory AS n \
+             INNER JOIN rsorm_tree AS t ON n.parent = t.id) SELECT * FROM rsorm_tree ORDER BY id ASC"
+        );
+        assert_eq!(ids(query.fetch().unwrap()), vec![4, 5]);
+
+        let query = db
+            .query::<testCategory>()
+            .descendants(testCategory::ID.eq(1))
+            .filter(testCategory::ID.ne(3))
+            .order_by_desc(testCategory::ID);
+        assert_eq!(ids(query.fetch().unwrap()), vec![5, 4, 2]);
+        let count = db
+            .query::<testCategory>()
+            .descendants(testCategory::ID.eq(1))
+            .count()
+            .unwrap();
+        assert_eq!(count, 4);
+
+        let out = db
+            .query::<testCategory>()
+            .ancestors(testCategory::NAME.eq("Category 5"))
+            .order_by(testCategory::ID)
+            .fetch()
+            .unwrap();
+        assert_eq!(ids(out), vec![1, 2, 4]);
+
+        let out = db
+            .query::<testCategory>()
+            .with_recursive(
+                "chain",
+                "SELECT * FROM testCategory WHERE id = ? \
+                 UNION ALL SELECT c.* FROM testCategory AS c JOIN chain ON c.id = chain.parent",
+                &[&5u64],
+            )
+            .filter(testCategory::ID.lt(5))
+            .order_by(testCategory::ID)
+            .fetch()
+            .unwrap();
+        assert_eq!(ids(out), vec![1, 2, 4]);
+
+        let out = db
+            .query::<testCategory>()
+            .with(
+                "roots",
+                "SELECT * FROM testCategory WHERE parent = ?",
+                &[&0u64],
+            )
+            .fetch()
+            .unwrap();
+        assert_eq!(ids(out), vec![1, 6]);
+
+        let result = db
+            .query::<testCategory>()
+            .descendants(Condition::eq("idd", 1))
+            .fetch();
+        assert!(matches!(result, Err(Error::InvalidColumn { .. })));
     }
 }
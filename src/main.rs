@@ -1,7 +1,7 @@
 use migrate_table::MigrateTable;
 use migrate_table_derive::MigrateTable;
+use rsorm::sql;
 use serde::Deserialize;
-mod lib;
 use std::fmt::Debug;
 
 #[allow(dead_code)]
@@ -21,7 +21,7 @@ struct BadModel {
 
 fn main() {
     // Usually we'll just be calling it as an argument to the create_table() method though
-    let mut db = lib::DB::new("some_dsn_here");
+    let db = rsorm::DB::new("some_dsn_here").unwrap();
 
     db.create_table(Model::generate_schema()).unwrap();
 
@@ -68,12 +68,14 @@ fn main() {
     );
     assert!(result2.is_err());
 
-    let mut out: Vec<Model> = Vec::new();
-    db.select_all("Model", &mut out).unwrap();
+    let out: Vec<Model> = db.select_all("Model").unwrap();
 
-    let mut filtered: Vec<Model> = Vec::new();
-    db.select_where("Model", &mut filtered, sql!(name = "Boris"))
-        .unwrap();
+    // Large tables can be streamed one row at a time instead of collected
+    for m in db.iter_all::<Model>("Model").unwrap() {
+        println!("streamed: {:?}", m.unwrap());
+    }
+
+    let filtered: Vec<Model> = db.select_where("Model", sql!(name = "Boris")).unwrap();
     println!("filtered: {:?}", filtered);
     println!(
         "IN:      {:?}\t{:?}\nDELETED: {:?}\nOUT: {:?}\nFILTERED: {:?}",
@@ -0,0 +1,293 @@
+use crate::cache;
+use crate::config::DbConfig;
+use crate::error::{Error, Result};
+use crate::{Tables, DB};
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+///
+/// A pool of `DB` connections to one database, for use from multiple threads.<br>
+/// Cloning the pool is cheap and every clone hands out connections from the same pool.<br>
+/// All connections share one table registry, so a table created through any of them
+/// can be used through the others.
+///
+#[derive(Clone)]
+pub struct DbPool {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    dsn: String,
+    config: DbConfig,
+    max_size: usize,
+    checkout_timeout: Duration,
+    pragmas: Vec<(String, String)>,
+    tables: Tables,
+    state: Mutex<State>,
+    returned: Condvar,
+}
+
+struct State {
+    idle: Vec<DB>,
+    open: usize,
+}
+
+///
+/// Configures a `DbPool`, created with `DbPool::builder`.
+///
+pub struct DbPoolBuilder {
+    dsn: String,
+    config: DbConfig,
+    tables: Option<Tables>,
+    max_size: usize,
+    checkout_timeout: Duration,
+    pragmas: Vec<(String, String)>,
+}
+
+impl DbPoolBuilder {
+    ///
+    /// The most connections the pool will open, 10 by default
+    ///
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size.max(1);
+        self
+    }
+
+    ///
+    /// How long `DbPool::get` waits for a connection to be returned when all are in use,
+    /// 30 seconds by default
+    ///
+    pub fn checkout_timeout(mut self, timeout: Duration) -> Self {
+        self.checkout_timeout = timeout;
+        self
+    }
+
+    ///
+    /// The settings every connection is opened with, see `DB::open_with`
+    ///
+    pub fn config(mut self, config: DbConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    ///
+    /// A pragma to run on every connection as it is opened, as `PRAGMA name = value`
+    ///
+    /// # Arguments
+    /// * `name` - the pragma, such as `foreign_keys`
+    /// * `value` - its value, such as `ON`
+    ///
+    pub fn pragma(mut self, name: &str, value: &str) -> Self {
+        self.pragmas.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// shares the table registry of another pool, so a table created through either is known to both
+    pub(crate) fn tables(mut self, tables: Tables) -> Self {
+        self.tables = Some(tables);
+        self
+    }
+
+    ///
+    /// Build the pool, opening its first connection so a bad dsn is reported here
+    ///
+    pub fn build(self) -> Result<DbPool> {
+        let pool = DbPool {
+            shared: Arc::new(Shared {
+                dsn: self.dsn,
+                config: self.config,
+                max_size: self.max_size,
+                checkout_timeout: self.checkout_timeout,
+                pragmas: self.pragmas,
+                tables: self.tables.unwrap_or_default(),
+                state: Mutex::new(State {
+                    idle: Vec::new(),
+                    open: 1,
+                }),
+                returned: Condvar::new(),
+            }),
+        };
+        let db = pool.shared.connect()?;
+        pool.shared.lock().idle.push(db);
+        Ok(pool)
+    }
+}
+
+impl DbPool {
+    ///
+    /// Create a pool with the default configuration
+    ///
+    /// # Arguments
+    /// * `dsn` - the database every connection opens, in any form accepted by `DB::open`.
+    ///   Note that every connection to `:memory:` is a separate database.
+    ///
+    pub fn new<P: AsRef<Path>>(dsn: P) -> Result<DbPool> {
+        DbPool::builder(dsn).build()
+    }
+
+    ///
+    /// Start configuring a pool
+    ///
+    /// # Arguments
+    /// * `dsn` - the database every connection opens, in any form accepted by `DB::open`
+    ///
+    pub fn builder<P: AsRef<Path>>(dsn: P) -> DbPoolBuilder {
+        DbPoolBuilder {
+            dsn: dsn.as_ref().to_string_lossy().into_owned(),
+            config: DbConfig::default(),
+            tables: None,
+            max_size: 10,
+            checkout_timeout: Duration::from_secs(30),
+            pragmas: Vec::new(),
+        }
+    }
+
+    ///
+    /// Check out a connection, opening a new one if none are idle and the pool isn't full.<br>
+    /// Waits up to the checkout timeout for a connection to be returned,
+    /// then fails with `Error::PoolTimeout`.
+    ///
+    pub fn get(&self) -> Result<PooledDb> {
+        let deadline = Instant::now() + self.shared.checkout_timeout;
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(db) = state.idle.pop() {
+                return Ok(self.pooled(db));
+            }
+
+            if state.open < self.shared.max_size {
+                state.open += 1;
+                drop(state);
+                return match self.shared.connect() {
+                    Ok(db) => Ok(self.pooled(db)),
+                    Err(e) => {
+                        self.shared.lock().open -= 1;
+                        self.shared.returned.notify_one();
+                        Err(e)
+                    }
+                };
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::PoolTimeout(self.shared.checkout_timeout));
+            }
+            state = self
+                .shared
+                .returned
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+
+    ///
+    /// The most connections the pool will open
+    ///
+    pub fn max_size(&self) -> usize {
+        self.shared.max_size
+    }
+
+    ///
+    /// The number of connections currently open, both idle and checked out
+    ///
+    pub fn open_connections(&self) -> usize {
+        self.shared.lock().open
+    }
+
+    ///
+    /// The number of open connections waiting to be checked out
+    ///
+    pub fn idle_connections(&self) -> usize {
+        self.shared.lock().idle.len()
+    }
+
+    pub(crate) fn shared_tables(&self) -> Tables {
+        Arc::clone(&self.shared.tables)
+    }
+
+    fn pooled(&self, db: DB) -> PooledDb {
+        PooledDb {
+            db: Some(db),
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl Shared {
+    fn connect(&self) -> Result<DB> {
+        let mut db = DB::open_with(&self.dsn, &self.config)?;
+        db.tables = Arc::clone(&self.tables);
+        for (name, value) in self.pragmas.iter() {
+            db.conn.execute(format!("PRAGMA {} = {}", name, value))?;
+        }
+        Ok(db)
+    }
+
+    // undoes what the last user changed on the connection, false if it can't be reused
+    fn reset(&self, db: &DB) -> bool {
+        if !db.is_autocommit() {
+            // an open transaction would hand its writes and locks to the next user
+            let _ = db.conn.execute("ROLLBACK");
+        }
+        db.tx_depth.set(0);
+        db.set_retry_policy(self.config.retry);
+        db.set_statement_cache_capacity(
+            self.config
+                .statement_cache_capacity
+                .unwrap_or(cache::DEFAULT_CAPACITY),
+        );
+        db.is_autocommit()
+    }
+
+    // a panic while the lock is held can't leave `State` half updated
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+///
+/// A connection checked out of a `DbPool`, returned to the pool when dropped.<br>
+/// A transaction left open is rolled back on return, and the retry policy and statement cache
+/// capacity are reset to the pool's configuration.<br>
+/// Derefs to `DB`, so the full API is available on it.
+///
+pub struct PooledDb {
+    db: Option<DB>,
+    shared: Arc<Shared>,
+}
+
+impl Deref for PooledDb {
+    type Target = DB;
+
+    fn deref(&self) -> &DB {
+        self.db
+            .as_ref()
+            .expect("connection is present until dropped")
+    }
+}
+
+impl DerefMut for PooledDb {
+    fn deref_mut(&mut self) -> &mut DB {
+        self.db
+            .as_mut()
+            .expect("connection is present until dropped")
+    }
+}
+
+impl Drop for PooledDb {
+    fn drop(&mut self) {
+        if let Some(db) = self.db.take() {
+            if self.shared.reset(&db) {
+                self.shared.lock().idle.push(db);
+            } else {
+                // closing the connection rolls back whatever it still has open
+                drop(db);
+                self.shared.lock().open -= 1;
+            }
+            self.shared.returned.notify_one();
+        }
+    }
+}
@@ -0,0 +1,723 @@
+use crate::aggregate::{self, Aggregate, Grouped};
+use crate::column::{Column, Projection};
+use crate::error::{Error, Result};
+use crate::iter::RowIter;
+use crate::join::{self, Join, Related};
+use crate::row::FromRow;
+use crate::sqlgen::{self, JoinClause, Select, With};
+use crate::value::{self, FromValue, ToValue, Value};
+use crate::DB;
+use migrate_table::MigrateTable;
+use std::marker::PhantomData;
+
+///
+/// The comparison of a column against a value in a `Condition`.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Like,
+}
+
+///
+/// A condition on a table's columns, used to filter a `Query`.<br>
+/// Built with the constructors below and combined with `and`, `or` and `!`.
+/// The records selected with `sql!` conditions can also be converted into one.
+///
+/// The `cond!` macro builds one from a model's typed columns, checked at compile time:
+///
+/// ```
+/// use migrate_table::MigrateTable;
+/// use migrate_table_derive::MigrateTable;
+/// use rsorm::cond;
+///
+/// #[derive(MigrateTable)]
+/// struct Person {
+///     name: String,
+///     age: u64,
+/// }
+///
+/// let n = "Jane";
+/// let condition = cond!(Person, age > 18 && name == n);
+/// ```
+///
+/// A misspelled column is reported at the typo:
+///
+/// ```compile_fail,E0599
+/// # use migrate_table::MigrateTable;
+/// # use migrate_table_derive::MigrateTable;
+/// # use rsorm::cond;
+/// # #[derive(MigrateTable)]
+/// # struct Person {
+/// #     name: String,
+/// #     age: u64,
+/// # }
+/// let condition = cond!(Person, aeg > 18);
+/// ```
+///
+/// And a value of the wrong type at the value:
+///
+/// ```compile_fail,E0277
+/// # use migrate_table::MigrateTable;
+/// # use migrate_table_derive::MigrateTable;
+/// # use rsorm::cond;
+/// # #[derive(MigrateTable)]
+/// # struct Person {
+/// #     name: String,
+/// #     age: u64,
+/// # }
+/// let condition = cond!(Person, age == "x");
+/// ```
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum Condition {
+    Compare {
+        column: String,
+        op: CompareOp,
+        value: Value,
+    },
+    IsNull {
+        column: String,
+        negated: bool,
+    },
+    In {
+        column: String,
+        values: Vec<Value>,
+        negated: bool,
+    },
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+    Not(Box<Condition>),
+    /// A comparison with a value sqlite can't store, such as a `u64` larger than `i64::MAX`,
+    /// reported as `Error::UnsupportedType` when the query runs
+    Unsupported {
+        column: String,
+        ty: String,
+    },
+}
+
+impl Condition {
+    pub(crate) fn compare<V: ToValue>(column: &str, op: CompareOp, value: V) -> Condition {
+        match value.to_value() {
+            // `= NULL` is never true in sql, so comparing with `None` tests for null instead
+            Ok(Value::Null) if op == CompareOp::Eq || op == CompareOp::Ne => Condition::IsNull {
+                column: column.to_string(),
+                negated: op == CompareOp::Ne,
+            },
+            Ok(value) => Condition::Compare {
+                column: column.to_string(),
+                op,
+                value,
+            },
+            Err(e) => Condition::unsupported(column, e),
+        }
+    }
+
+    fn unsupported(column: &str, error: Error) -> Condition {
+        let ty = match error {
+            Error::UnsupportedType { ty, .. } => ty,
+            e => e.to_string(),
+        };
+        Condition::Unsupported {
+            column: column.to_string(),
+            ty,
+        }
+    }
+
+    /// `column = value`, or `column IS NULL` when `value` is null
+    pub fn eq<V: ToValue>(column: &str, value: V) -> Condition {
+        Condition::compare(column, CompareOp::Eq, value)
+    }
+
+    /// `column != value`, or `column IS NOT NULL` when `value` is null
+    pub fn ne<V: ToValue>(column: &str, value: V) -> Condition {
+        Condition::compare(column, CompareOp::Ne, value)
+    }
+
+    /// `column < value`
+    pub fn lt<V: ToValue>(column: &str, value: V) -> Condition {
+        Condition::compare(column, CompareOp::Lt, value)
+    }
+
+    /// `column <= value`
+    pub fn le<V: ToValue>(column: &str, value: V) -> Condition {
+        Condition::compare(column, CompareOp::Le, value)
+    }
+
+    /// `column > value`
+    pub fn gt<V: ToValue>(column: &str, value: V) -> Condition {
+        Condition::compare(column, CompareOp::Gt, value)
+    }
+
+    /// `column >= value`
+    pub fn ge<V: ToValue>(column: &str, value: V) -> Condition {
+        Condition::compare(column, CompareOp::Ge, value)
+    }
+
+    /// `column LIKE pattern`, where `%` matches any run of characters and `_` any one
+    pub fn like(column: &str, pattern: &str) -> Condition {
+        Condition::compare(column, CompareOp::Like, pattern)
+    }
+
+    /// `column IS NULL`
+    pub fn is_null(column: &str) -> Condition {
+        Condition::IsNull {
+            column: column.to_string(),
+            negated: false,
+        }
+    }
+
+    /// `column IS NOT NULL`
+    pub fn is_not_null(column: &str) -> Condition {
+        Condition::IsNull {
+            column: column.to_string(),
+            negated: true,
+        }
+    }
+
+    /// `column IN (values...)`, never true for an empty list
+    pub fn in_<V, I>(column: &str, values: I) -> Condition
+    where
+        V: ToValue,
+        I: IntoIterator<Item = V>,
+    {
+        match values.into_iter().map(|v| v.to_value()).collect() {
+            Ok(values) => Condition::In {
+                column: column.to_string(),
+                values,
+                negated: false,
+            },
+            Err(e) => Condition::unsupported(column, e),
+        }
+    }
+
+    ///
+    /// Both this condition and `other`
+    ///
+    pub fn and(self, other: Condition) -> Condition {
+        match self {
+            Condition::And(mut conditions) => {
+                conditions.push(other);
+                Condition::And(conditions)
+            }
+            this => Condition::And(vec![this, other]),
+        }
+    }
+
+    ///
+    /// Either this condition or `other`
+    ///
+    pub fn or(self, other: Condition) -> Condition {
+        match self {
+            Condition::Or(mut conditions) => {
+                conditions.push(other);
+                Condition::Or(conditions)
+            }
+            this => Condition::Or(vec![this, other]),
+        }
+    }
+
+    /// the condition with each column prefixed by `qualifier`, for selects over several tables
+    pub(crate) fn qualified(&self, qualifier: &str) -> Condition {
+        let qualify = |column: &String| format!("{}.{}", qualifier, column);
+        match self {
+            Condition::Compare { column, op, value } => Condition::Compare {
+                column: qualify(column),
+                op: *op,
+                value: value.clone(),
+            },
+            Condition::IsNull { column, negated } => Condition::IsNull {
+                column: qualify(column),
+                negated: *negated,
+            },
+            Condition::In {
+                column,
+                values,
+                negated,
+            } => Condition::In {
+                column: qualify(column),
+                values: values.clone(),
+                negated: *negated,
+            },
+            Condition::And(conditions) => {
+                Condition::And(conditions.iter().map(|c| c.qualified(qualifier)).collect())
+            }
+            Condition::Or(conditions) => {
+                Condition::Or(conditions.iter().map(|c| c.qualified(qualifier)).collect())
+            }
+            Condition::Not(condition) => Condition::Not(Box::new(condition.qualified(qualifier))),
+            Condition::Unsupported { column, ty } => Condition::Unsupported {
+                column: qualify(column),
+                ty: ty.clone(),
+            },
+        }
+    }
+
+    /// the first value in the condition that couldn't be converted, as an error
+    pub(crate) fn check_values(&self) -> Result<()> {
+        match self {
+            Condition::Unsupported { column, ty } => Err(Error::UnsupportedType {
+                column: column.clone(),
+                ty: ty.clone(),
+            }),
+            Condition::And(conditions) | Condition::Or(conditions) => {
+                conditions.iter().try_for_each(Condition::check_values)
+            }
+            Condition::Not(condition) => condition.check_values(),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn collect_columns(&self, columns: &mut Vec<String>) {
+        match self {
+            Condition::Compare { column, .. }
+            | Condition::IsNull { column, .. }
+            | Condition::In { column, .. }
+            | Condition::Unsupported { column, .. } => columns.push(column.clone()),
+            Condition::And(conditions) | Condition::Or(conditions) => {
+                for condition in conditions {
+                    condition.collect_columns(columns);
+                }
+            }
+            Condition::Not(condition) => condition.collect_columns(columns),
+        }
+    }
+}
+
+impl std::ops::Not for Condition {
+    type Output = Condition;
+
+    fn not(self) -> Condition {
+        Condition::Not(Box::new(self))
+    }
+}
+
+/// conditions written with the `sql` macro, where every `column = value` must hold
+impl From<(Vec<String>, Vec<String>)> for Condition {
+    fn from(data: (Vec<String>, Vec<String>)) -> Self {
+        let conditions = data
+            .0
+            .iter()
+            .zip(data.1.iter())
+            .map(|(column, literal)| Condition::eq(column, Value::from_literal(literal)))
+            .collect();
+        Condition::And(conditions)
+    }
+}
+
+///
+/// Whether a `Query` sorts a column ascending or descending.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+///
+/// A select on the table of model `T`, created with `DB::query`.<br>
+/// Filters, ordering and limits are added with chained calls, then the query is run
+/// with `fetch` or `iter`. Values are always bound as statement parameters.
+///
+pub struct Query<'db, T> {
+    db: &'db DB,
+    select: Select,
+    phantom: PhantomData<T>,
+}
+
+impl<'db, T> Query<'db, T>
+where
+    T: FromRow,
+{
+    pub(crate) fn new(db: &'db DB, table: &str) -> Self {
+        Query {
+            db,
+            select: Select::new(table),
+            phantom: PhantomData,
+        }
+    }
+
+    ///
+    /// Only select records matching `condition`, multiple filters must all match
+    ///
+    /// # Arguments
+    /// * `condition` - a `Condition`, or conditions written with the `sql` macro
+    ///
+    pub fn filter<C: Into<Condition>>(mut self, condition: C) -> Self {
+        self.select.filter.push(condition.into());
+        self
+    }
+
+    ///
+    /// Select only `columns`, reading each record into their values
+    ///
+    /// # Arguments
+    /// * `columns` - a column of `T`, like `Person::NAME`, or a tuple of them,
+    ///   like `(Person::NAME, Person::AGE)` selecting `(String, u64)` records
+    ///
+    pub fn select<S>(self, columns: S) -> Query<'db, S::Output>
+    where
+        S: Projection<T>,
+        S::Output: FromRow,
+    {
+        self.select_into(&columns.columns())
+    }
+
+    ///
+    /// Select only `columns`, reading each record into `P`.<br>
+    /// `P` can be a struct with a subset of `T`'s fields, read by name, or a tuple read in
+    /// column order.
+    ///
+    /// # Arguments
+    /// * `columns` - the names of the columns to select
+    ///
+    pub fn select_into<P, C>(mut self, columns: &[C]) -> Query<'db, P>
+    where
+        P: FromRow,
+        C: AsRef<str>,
+    {
+        self.select.columns = columns.iter().map(|c| c.as_ref().to_string()).collect();
+        Query {
+            db: self.db,
+            select: self.select,
+            phantom: PhantomData,
+        }
+    }
+
+    ///
+    /// Read the records from a common table expression written in sql, `WITH name AS (sql)`,
+    /// instead of `T`'s table.<br>
+    /// Its records must have `T`'s columns, which filters and ordering are checked against.
+    ///
+    /// # Arguments
+    /// * `name` - the name the expression is given in the query
+    /// * `sql` - the select defining the records, with a `?` for each parameter
+    /// * `params` - the values bound to the parameters, in order
+    ///
+    pub fn with(self, name: &str, sql: &str, params: &[&dyn ToValue]) -> Self {
+        self.with_expression(false, name, sql, params)
+    }
+
+    ///
+    /// Like `with`, for a recursive expression, `WITH RECURSIVE name AS (sql)`, whose sql
+    /// refers to `name` to select records from the records already selected
+    ///
+    /// # Arguments
+    /// * `name` - the name the expression is given in the query
+    /// * `sql` - the select defining the records, with a `?` for each parameter
+    /// * `params` - the values bound to the parameters, in order
+    ///
+    pub fn with_recursive(self, name: &str, sql: &str, params: &[&dyn ToValue]) -> Self {
+        self.with_expression(true, name, sql, params)
+    }
+
+    fn with_expression(
+        mut self,
+        recursive: bool,
+        name: &str,
+        sql: &str,
+        params: &[&dyn ToValue],
+    ) -> Self {
+        let (params, conditions) = match value::param_values(params) {
+            Ok(params) => (params, Vec::new()),
+            Err(e) => (Vec::new(), vec![Condition::unsupported(name, e)]),
+        };
+        self.select.with = Some(With {
+            recursive,
+            name: name.to_string(),
+            sql: sql.to_string(),
+            params,
+            columns: Vec::new(),
+            conditions,
+        });
+        self
+    }
+
+    ///
+    /// Read the descendants of the records matching `root` instead of every record, following
+    /// the parent key `T` declares with `Related<T>`.<br>
+    /// Filters, ordering and limits then apply to the descendants, and the roots themselves
+    /// are only included when they descend from another root.
+    ///
+    /// # Arguments
+    /// * `root` - the records whose children, grandchildren and so on are selected
+    ///
+    pub fn descendants<C: Into<Condition>>(self, root: C) -> Self
+    where
+        T: Related<T>,
+    {
+        self.tree(root.into(), true)
+    }
+
+    ///
+    /// Read the ancestors of the records matching `node` instead of every record, following
+    /// the parent key `T` declares with `Related<T>`
+    ///
+    /// # Arguments
+    /// * `node` - the records whose parent, grandparent and so on are selected
+    ///
+    pub fn ancestors<C: Into<Condition>>(self, node: C) -> Self
+    where
+        T: Related<T>,
+    {
+        self.tree(node.into(), false)
+    }
+
+    fn tree(mut self, start: Condition, descendants: bool) -> Self
+    where
+        T: Related<T>,
+    {
+        let (parent, key) = T::join_columns();
+        self.select.with = Some(sqlgen::tree(
+            &self.select.table,
+            parent,
+            key,
+            &start,
+            descendants,
+        ));
+        self
+    }
+
+    ///
+    /// Sort by `column` ascending, after any earlier orderings
+    ///
+    pub fn order_by<C: AsRef<str>>(mut self, column: C) -> Self {
+        self.select
+            .order
+            .push((column.as_ref().to_string(), Order::Asc));
+        self
+    }
+
+    ///
+    /// Sort by `column` descending, after any earlier orderings
+    ///
+    pub fn order_by_desc<C: AsRef<str>>(mut self, column: C) -> Self {
+        self.select
+            .order
+            .push((column.as_ref().to_string(), Order::Desc));
+        self
+    }
+
+    ///
+    /// Select at most `limit` records
+    ///
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.select.limit = Some(limit);
+        self
+    }
+
+    ///
+    /// Skip the first `offset` records
+    ///
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.select.offset = Some(offset);
+        self
+    }
+
+    ///
+    /// The sql this query runs, with a `?` for each bound parameter
+    ///
+    pub fn to_sql(&self) -> String {
+        self.select.to_sql().0
+    }
+
+    ///
+    /// The values bound to the query's parameters, in order
+    ///
+    pub fn params(&self) -> Vec<Value> {
+        self.select.to_sql().1
+    }
+
+    ///
+    /// Run the query, collecting every matching record
+    ///
+    pub fn fetch(self) -> Result<Vec<T>> {
+        self.iter()?.collect()
+    }
+
+    ///
+    /// Run the query, reading matching records lazily as the iterator is advanced
+    ///
+    pub fn iter(self) -> Result<RowIter<'db, T>> {
+        self.db.select(&self.select)
+    }
+
+    ///
+    /// The first matching record, `None` when nothing matches
+    ///
+    pub fn first(mut self) -> Result<Option<T>> {
+        self.select.limit = Some(1);
+        self.iter()?.next().transpose()
+    }
+
+    ///
+    /// The only matching record, `None` when nothing matches.<br>
+    /// Fails with `Error::TooManyRows` when more than one record matches.
+    ///
+    pub fn find_one(mut self) -> Result<Option<T>> {
+        // a second row is all it takes to know the match isn't unique
+        self.select.limit = Some(self.select.limit.map_or(2, |limit| limit.min(2)));
+        let table = self.select.table.clone();
+        let mut rows = self.iter()?;
+        let first = rows.next().transpose()?;
+        match rows.next() {
+            Some(_) => Err(Error::TooManyRows(table)),
+            None => Ok(first),
+        }
+    }
+
+    ///
+    /// The only matching record.<br>
+    /// Fails with `Error::NotFound` when nothing matches, and `Error::TooManyRows` when more
+    /// than one record matches.
+    ///
+    pub fn get(self) -> Result<T> {
+        let table = self.select.table.clone();
+        self.find_one()?.ok_or(Error::NotFound(table))
+    }
+
+    ///
+    /// Whether any record matches
+    ///
+    pub fn exists(mut self) -> Result<bool> {
+        self.select.limit = Some(1);
+        let mut rows = self.db.select_with(&self.select, |_, _| Ok(()), 0)?;
+        rows.next().transpose().map(|row| row.is_some())
+    }
+
+    ///
+    /// The number of matching records
+    ///
+    pub fn count(self) -> Result<i64> {
+        self.aggregate(Aggregate::count())
+    }
+
+    ///
+    /// The sum of `column` over the matching records, `None` when there are none
+    ///
+    pub fn sum<V: FromValue>(self, column: Column<T, V>) -> Result<Option<V>> {
+        self.aggregate(Aggregate::sum(column))
+    }
+
+    ///
+    /// The average of `column` over the matching records, `None` when there are none
+    ///
+    pub fn avg<V>(self, column: Column<T, V>) -> Result<Option<f64>> {
+        self.aggregate(Aggregate::avg(column))
+    }
+
+    ///
+    /// The smallest value of `column` in the matching records, `None` when there are none
+    ///
+    pub fn min<V: FromValue>(self, column: Column<T, V>) -> Result<Option<V>> {
+        self.aggregate(Aggregate::min(column))
+    }
+
+    ///
+    /// The largest value of `column` in the matching records, `None` when there are none
+    ///
+    pub fn max<V: FromValue>(self, column: Column<T, V>) -> Result<Option<V>> {
+        self.aggregate(Aggregate::max(column))
+    }
+
+    ///
+    /// Compute `aggregate` over the matching records, read as `R`.<br>
+    /// Any ordering, limit and offset are ignored.
+    ///
+    pub fn aggregate<R: FromValue>(self, aggregate: Aggregate) -> Result<R> {
+        aggregate::scalar(self.db, self.select, aggregate)
+    }
+
+    ///
+    /// Join the table of model `B` on `column` equal to `joined`, selecting `(T, B)` pairs
+    /// for the records with a match
+    ///
+    /// # Arguments
+    /// * `column` - a column of `T`
+    /// * `joined` - a column of `B` holding the same type of value
+    ///
+    pub fn join<B, K>(self, column: Column<T, K>, joined: Column<B, K>) -> Join<'db, T, B>
+    where
+        B: MigrateTable + FromRow,
+    {
+        let join = join_clause::<B>(column.name(), joined.name(), false);
+        Join::new(self.db, self.select, join, join::pair)
+    }
+
+    ///
+    /// Left join the table of model `B` on `column` equal to `joined`, selecting
+    /// `(T, Option<B>)` pairs where records without a match are paired with `None`
+    ///
+    /// # Arguments
+    /// * `column` - a column of `T`
+    /// * `joined` - a column of `B` holding the same type of value
+    ///
+    pub fn left_join<B, K>(
+        self,
+        column: Column<T, K>,
+        joined: Column<B, K>,
+    ) -> Join<'db, T, Option<B>>
+    where
+        B: MigrateTable + FromRow,
+    {
+        let join = join_clause::<B>(column.name(), joined.name(), true);
+        Join::new(self.db, self.select, join, join::left_pair)
+    }
+
+    ///
+    /// Like `join`, on the key declared by `T`'s `Related<B>` implementation
+    ///
+    pub fn join_related<B>(self) -> Join<'db, T, B>
+    where
+        T: Related<B>,
+        B: MigrateTable + FromRow,
+    {
+        let (column, joined) = T::join_columns();
+        Join::new(
+            self.db,
+            self.select,
+            join_clause::<B>(column, joined, false),
+            join::pair,
+        )
+    }
+
+    ///
+    /// Like `left_join`, on the key declared by `T`'s `Related<B>` implementation
+    ///
+    pub fn left_join_related<B>(self) -> Join<'db, T, Option<B>>
+    where
+        T: Related<B>,
+        B: MigrateTable + FromRow,
+    {
+        let (column, joined) = T::join_columns();
+        Join::new(
+            self.db,
+            self.select,
+            join_clause::<B>(column, joined, true),
+            join::left_pair,
+        )
+    }
+
+    ///
+    /// Group the matching records by `column`, to compute aggregates for each group
+    ///
+    pub fn group_by<K: FromValue>(self, column: Column<T, K>) -> Grouped<'db, T, K> {
+        Grouped::new(self.db, self.select, column.name())
+    }
+}
+
+fn join_clause<B: MigrateTable>(column: &str, joined: &str, left: bool) -> JoinClause {
+    JoinClause {
+        table: B::generate_schema().0,
+        left,
+        on: (column.to_string(), joined.to_string()),
+        columns: Vec::new(),
+        filter: Vec::new(),
+        order: Vec::new(),
+    }
+}
@@ -0,0 +1,132 @@
+use crate::error::{Error, Result};
+use std::time::Duration;
+
+///
+/// How a `DB` retries writes and transactions that fail because the database is locked.<br>
+/// Attempts are spaced by a backoff that doubles after each attempt, up to a maximum.
+/// When every attempt fails, the last error is reported in `Error::RetriesExhausted`.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    ///
+    /// A policy making up to `max_attempts` attempts, backing off from 10ms up to 1s
+    ///
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+
+    ///
+    /// Set the wait before the first retry, and the most to wait before any retry
+    ///
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// the wait after failed attempt number `attempt`, counting from 1
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt - 1).unwrap_or(u32::MAX);
+        self.initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |d| d.min(self.max_backoff))
+    }
+}
+
+/// runs `op`, retrying it under `policy` for as long as it fails because the database is locked
+pub(crate) fn run<T, F>(policy: Option<RetryPolicy>, mut op: F) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+{
+    let policy = match policy {
+        Some(policy) => policy,
+        None => return op(),
+    };
+
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Err(e) if e.is_busy() => {
+                if attempt >= policy.max_attempts {
+                    return Err(Error::RetriesExhausted {
+                        attempts: attempt,
+                        error: Box::new(e),
+                    });
+                }
+                std::thread::sleep(policy.delay(attempt));
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn busy() -> Error {
+        Error::from(sqlite::Error {
+            code: Some(5),
+            message: Some(String::from("database is locked")),
+        })
+    }
+
+    #[test]
+    fn test_delay_doubles_up_to_max() {
+        let policy =
+            RetryPolicy::new(10).backoff(Duration::from_millis(5), Duration::from_millis(30));
+        let delays: Vec<u128> = (1..6).map(|a| policy.delay(a).as_millis()).collect();
+        assert_eq!(delays, vec![5, 10, 20, 30, 30]);
+        assert_eq!(policy.delay(40), Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_retries_busy_errors() {
+        let policy =
+            RetryPolicy::new(3).backoff(Duration::from_millis(1), Duration::from_millis(1));
+
+        let mut calls = 0;
+        let result = run(Some(policy), || {
+            calls += 1;
+            if calls < 3 {
+                Err(busy())
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let mut calls = 0;
+        let result: Result<()> = run(Some(policy), || {
+            calls += 1;
+            Err(busy())
+        });
+        assert!(matches!(
+            result,
+            Err(Error::RetriesExhausted { attempts: 3, .. })
+        ));
+        assert_eq!(calls, 3);
+
+        let mut calls = 0;
+        let result: Result<()> = run(Some(policy), || {
+            calls += 1;
+            Err(Error::UnknownTable(String::from("Person")))
+        });
+        assert!(matches!(result, Err(Error::UnknownTable(_))));
+        assert_eq!(calls, 1);
+
+        let result: Result<()> = run(None, || Err(busy()));
+        assert!(result.unwrap_err().is_busy());
+    }
+}
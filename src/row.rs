@@ -0,0 +1,204 @@
+use crate::de::RowDeserializer;
+use crate::error::Error;
+use crate::value::{FromValue, Value};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+///
+/// A borrowed view of the row a select is currently positioned on.<br>
+/// Columns are looked up by name with typed getters.
+///
+pub struct RowRef<'r, 'l> {
+    stmt: &'r sqlite::Statement<'l>,
+    columns: &'r [String],
+    /// the index in the statement of the first column in `columns`
+    offset: usize,
+}
+
+impl<'r, 'l> RowRef<'r, 'l> {
+    pub(crate) fn new(stmt: &'r sqlite::Statement<'l>, columns: &'r [String]) -> Self {
+        RowRef {
+            stmt,
+            columns,
+            offset: 0,
+        }
+    }
+
+    /// splits the row before column `at`, so each model of a join sees only its own columns
+    pub(crate) fn split(&self, at: usize) -> (RowRef<'r, 'l>, RowRef<'r, 'l>) {
+        let at = at.min(self.columns.len());
+        let (left, right) = self.columns.split_at(at);
+        (
+            RowRef {
+                stmt: self.stmt,
+                columns: left,
+                offset: self.offset,
+            },
+            RowRef {
+                stmt: self.stmt,
+                columns: right,
+                offset: self.offset + at,
+            },
+        )
+    }
+
+    /// whether every column is null, as in the missing side of a left join
+    pub(crate) fn is_null(&self) -> bool {
+        (0..self.columns.len())
+            .all(|i| self.stmt.column_type(self.offset + i) == sqlite::Type::Null)
+    }
+
+    ///
+    /// The names of the columns in this row, in select order
+    ///
+    pub fn columns(&self) -> &[String] {
+        self.columns
+    }
+
+    ///
+    /// Read the named column as `T`
+    ///
+    /// # Arguments
+    /// * `name` - the column to read
+    ///
+    pub fn get<T: FromValue>(&self, name: &str) -> Result<T, Error> {
+        let i = self
+            .columns
+            .iter()
+            .position(|c| c == name)
+            .ok_or_else(|| Error::Decode(format!("row does not contain column {}", name)))?;
+        self.read(i)
+    }
+
+    /// reads the column at index `i`, for columns named after an expression rather than a field
+    pub(crate) fn read<T: FromValue>(&self, i: usize) -> Result<T, Error> {
+        let value = self.stmt.read::<sqlite::Value>(self.offset + i)?;
+        T::from_value(Value::from(value)).map_err(|e| match e {
+            Error::Decode(message) => {
+                Error::Decode(format!("column {}: {}", self.columns[i], message))
+            }
+            e => e,
+        })
+    }
+
+    pub(crate) fn deserializer(&self) -> RowDeserializer<'r, 'l> {
+        RowDeserializer::new(self.stmt, self.columns).with_offset(self.offset)
+    }
+}
+
+///
+/// An owned row read without a model, for tables whose columns are only known at runtime.<br>
+/// Returned by `DB::select_rows`, and by any query read into `Row`. Columns are read by
+/// name as a `Value`, or as any `FromValue` type with `get`.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Row {
+    columns: Vec<String>,
+    values: Vec<Value>,
+}
+
+impl Row {
+    ///
+    /// The names of the columns in this row, in select order
+    ///
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    ///
+    /// The values of the columns in this row, in select order
+    ///
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    ///
+    /// The value of the named column, `None` if the row doesn't have it
+    ///
+    pub fn value(&self, name: &str) -> Option<&Value> {
+        let i = self.columns.iter().position(|c| c == name)?;
+        self.values.get(i)
+    }
+
+    ///
+    /// Read the named column as `T`
+    ///
+    /// # Arguments
+    /// * `name` - the column to read
+    ///
+    pub fn get<T: FromValue>(&self, name: &str) -> Result<T, Error> {
+        let value = self
+            .value(name)
+            .ok_or_else(|| Error::Decode(format!("row does not contain column {}", name)))?;
+        T::from_value(value.clone()).map_err(|e| match e {
+            Error::Decode(message) => Error::Decode(format!("column {}: {}", name, message)),
+            e => e,
+        })
+    }
+
+    ///
+    /// Iterate over the row's column names and values
+    ///
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.columns
+            .iter()
+            .map(String::as_str)
+            .zip(self.values.iter())
+    }
+
+    ///
+    /// The row's columns paired with their values
+    ///
+    pub fn into_pairs(self) -> Vec<(String, Value)> {
+        self.columns.into_iter().zip(self.values).collect()
+    }
+}
+
+impl FromRow for Row {
+    fn from_row(row: &RowRef) -> Result<Self, Error> {
+        let values = (0..row.columns.len())
+            .map(|i| row.read(i))
+            .collect::<Result<_, Error>>()?;
+        Ok(Row {
+            columns: row.columns.to_vec(),
+            values,
+        })
+    }
+}
+
+/// rows can be inserted as they were read, such as into another table
+impl ToRow for Row {
+    fn to_row(&self) -> Result<Vec<(String, Value)>, Error> {
+        Ok(self.clone().into_pairs())
+    }
+}
+
+///
+/// Builds a model from a selected row.<br>
+/// Implemented for every serde `Deserialize` type, and generated by
+/// `#[derive(FromRow)]` for models that don't use serde.
+///
+pub trait FromRow: Sized {
+    fn from_row(row: &RowRef) -> Result<Self, Error>;
+}
+
+impl<T: DeserializeOwned> FromRow for T {
+    fn from_row(row: &RowRef) -> Result<Self, Error> {
+        T::deserialize(row.deserializer())
+    }
+}
+
+///
+/// Converts a model into the column/value pairs to insert.<br>
+/// Implemented for every serde `Serialize` type, and generated by
+/// `#[derive(ToRow)]` for models that don't use serde.
+///
+pub trait ToRow {
+    fn to_row(&self) -> Result<Vec<(String, Value)>, Error>;
+}
+
+impl<T: Serialize> ToRow for T {
+    fn to_row(&self) -> Result<Vec<(String, Value)>, Error> {
+        crate::ser::to_row(self)
+    }
+}
@@ -0,0 +1,296 @@
+use crate::config::{DbConfig, JournalMode};
+use crate::dsn::{Dsn, Mode};
+use crate::error::{Error, Result};
+use crate::pool::{DbPool, PooledDb};
+use crate::query::Condition;
+use crate::row::{FromRow, Row, ToRow};
+use crate::transaction::Transaction;
+use crate::value::ToValue;
+use crate::DB;
+use migrate_table::MigrateTable;
+use std::path::Path;
+use std::time::Duration;
+
+///
+/// A database in WAL mode opened with one writer connection and a pool of read-only readers.<br>
+/// Selects and lookups run on the readers and writes and transactions on the writer, so reads
+/// never queue behind writes and each reader sees the last committed state.<br>
+/// Queries built with `DB::query` run on a reader through `read`, or on whichever connection
+/// `reader` or `writer` checked out.<br>
+/// Like `DbPool`, it can be shared between threads, and all its connections share one table registry.
+///
+pub struct SplitDb {
+    writer: DbPool,
+    readers: DbPool,
+}
+
+///
+/// Configures a `SplitDb`, created with `SplitDb::builder`.
+///
+pub struct SplitDbBuilder {
+    dsn: String,
+    config: DbConfig,
+    readers: usize,
+    checkout_timeout: Duration,
+}
+
+impl SplitDbBuilder {
+    ///
+    /// The settings every connection is opened with, see `DB::open_with`.<br>
+    /// The journal mode is always WAL, and readers are always opened read-only.
+    ///
+    pub fn config(mut self, config: DbConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    ///
+    /// The most reader connections to open, 4 by default
+    ///
+    pub fn readers(mut self, readers: usize) -> Self {
+        self.readers = readers;
+        self
+    }
+
+    ///
+    /// How long to wait for the writer or a reader when all are in use, 30 seconds by default
+    ///
+    pub fn checkout_timeout(mut self, timeout: Duration) -> Self {
+        self.checkout_timeout = timeout;
+        self
+    }
+
+    ///
+    /// Open the writer, switching the database to WAL mode, then the first reader
+    ///
+    pub fn build(self) -> Result<SplitDb> {
+        if Dsn::parse(&self.dsn)?.mode == Mode::Memory {
+            return Err(Error::InvalidDsn(String::from(
+                "a split DB needs a database file its connections can share",
+            )));
+        }
+
+        let writer = DbPool::builder(&self.dsn)
+            .config(self.config.clone().journal_mode(JournalMode::Wal))
+            .max_size(1)
+            .checkout_timeout(self.checkout_timeout)
+            .build()?;
+
+        let mut reader_config = self.config.read_only();
+        // the journal mode is stored in the file, and readers can't change it
+        reader_config.journal_mode = None;
+        let readers = DbPool::builder(&self.dsn)
+            .config(reader_config)
+            .tables(writer.shared_tables())
+            .max_size(self.readers)
+            .checkout_timeout(self.checkout_timeout)
+            .build()?;
+        Ok(SplitDb { writer, readers })
+    }
+}
+
+impl SplitDb {
+    ///
+    /// Open a split DB with the default configuration
+    ///
+    /// # Arguments
+    /// * `dsn` - a database file, in any form accepted by `DB::open` other than `:memory:`
+    ///
+    pub fn open<P: AsRef<Path>>(dsn: P) -> Result<SplitDb> {
+        SplitDb::builder(dsn).build()
+    }
+
+    ///
+    /// Start configuring a split DB
+    ///
+    /// # Arguments
+    /// * `dsn` - a database file, in any form accepted by `DB::open` other than `:memory:`
+    ///
+    pub fn builder<P: AsRef<Path>>(dsn: P) -> SplitDbBuilder {
+        SplitDbBuilder {
+            dsn: dsn.as_ref().to_string_lossy().into_owned(),
+            config: DbConfig::default(),
+            readers: 4,
+            checkout_timeout: Duration::from_secs(30),
+        }
+    }
+
+    ///
+    /// Check out the writer connection, waiting while another thread has it
+    ///
+    pub fn writer(&self) -> Result<PooledDb> {
+        self.writer.get()
+    }
+
+    ///
+    /// Check out a read-only reader connection, for streaming selects with `iter_where`
+    ///
+    pub fn reader(&self) -> Result<PooledDb> {
+        self.readers.get()
+    }
+
+    ///
+    /// See `DB::create_table`
+    ///
+    pub fn create_table(&self, schema: (String, Vec<(String, String)>)) -> Result<()> {
+        self.writer()?.create_table(schema)
+    }
+
+    ///
+    /// See `DB::insert`
+    ///
+    pub fn insert(&self, table: &str, data: (Vec<String>, Vec<String>)) -> Result<()> {
+        self.writer()?.insert(table, data)
+    }
+
+    ///
+    /// See `DB::insert_model`
+    ///
+    pub fn insert_model<T: ToRow>(&self, table: &str, model: &T) -> Result<()> {
+        self.writer()?.insert_model(table, model)
+    }
+
+    ///
+    /// See `DB::insert_many`
+    ///
+    pub fn insert_many<T: ToRow>(&self, table: &str, models: &[T]) -> Result<usize> {
+        self.writer()?.insert_many(table, models)
+    }
+
+    ///
+    /// See `DB::insert_values`
+    ///
+    pub fn insert_values(
+        &self,
+        table: &str,
+        rows: Vec<(Vec<String>, Vec<String>)>,
+    ) -> Result<usize> {
+        self.writer()?.insert_values(table, rows)
+    }
+
+    ///
+    /// See `DB::select_where`, runs on a reader
+    ///
+    pub fn select_where<T: FromRow>(
+        &self,
+        table: &str,
+        data: (Vec<String>, Vec<String>),
+    ) -> Result<Vec<T>> {
+        self.reader()?.select_where(table, data)
+    }
+
+    ///
+    /// See `DB::select_all`, runs on a reader
+    ///
+    pub fn select_all<T: FromRow>(&self, table: &str) -> Result<Vec<T>> {
+        self.reader()?.select_all(table)
+    }
+
+    ///
+    /// See `DB::select_rows`, runs on a reader
+    ///
+    pub fn select_rows(&self, table: &str, condition: impl Into<Condition>) -> Result<Vec<Row>> {
+        self.reader()?.select_rows(table, condition)
+    }
+
+    ///
+    /// See `DB::find_one`, runs on a reader
+    ///
+    pub fn find_one<T: FromRow>(
+        &self,
+        table: &str,
+        condition: impl Into<Condition>,
+    ) -> Result<Option<T>> {
+        self.reader()?.find_one(table, condition)
+    }
+
+    ///
+    /// See `DB::get`, runs on a reader
+    ///
+    pub fn get<T: FromRow>(&self, table: &str, condition: impl Into<Condition>) -> Result<T> {
+        self.reader()?.get(table, condition)
+    }
+
+    ///
+    /// See `DB::exists`, runs on a reader
+    ///
+    pub fn exists(&self, table: &str, condition: impl Into<Condition>) -> Result<bool> {
+        self.reader()?.exists(table, condition)
+    }
+
+    ///
+    /// See `DB::count`, runs on a reader
+    ///
+    pub fn count<T>(&self, condition: impl Into<Condition>) -> Result<i64>
+    where
+        T: MigrateTable + FromRow,
+    {
+        self.reader()?.count::<T>(condition)
+    }
+
+    ///
+    /// See `DB::query_as`, runs on a reader, which fails to write
+    ///
+    pub fn query_as<T: FromRow>(&self, sql: &str, params: &[&dyn ToValue]) -> Result<Vec<T>> {
+        self.reader()?.query_as(sql, params)
+    }
+
+    ///
+    /// Run `f` with a reader, for queries built with `DB::query` and `DB::query_table`.<br>
+    /// The reader is returned to the pool once `f` returns.
+    ///
+    /// # Arguments
+    /// * `f` - the reads to run
+    ///
+    pub fn read<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&DB) -> Result<R>,
+    {
+        let reader = self.reader()?;
+        f(&reader)
+    }
+
+    ///
+    /// See `DB::execute`, runs on the writer
+    ///
+    pub fn execute(&self, sql: &str, params: &[&dyn ToValue]) -> Result<usize> {
+        self.writer()?.execute(sql, params)
+    }
+
+    ///
+    /// See `DB::delete`
+    ///
+    pub fn delete(&self, table: &str, data: (Vec<String>, Vec<String>)) -> Result<()> {
+        self.writer()?.delete(table, data)
+    }
+
+    ///
+    /// See `DB::delete_all`
+    ///
+    pub fn delete_all(&self, table: &str) -> Result<()> {
+        self.writer()?.delete_all(table)
+    }
+
+    ///
+    /// See `DB::update`
+    ///
+    pub fn update(
+        &self,
+        table: &str,
+        data: (Vec<String>, Vec<String>),
+        conditions: (Vec<String>, Vec<String>),
+    ) -> Result<()> {
+        self.writer()?.update(table, data, conditions)
+    }
+
+    ///
+    /// See `DB::transaction`, runs on the writer so its selects see its own writes
+    ///
+    pub fn transaction<F, T, E>(&self, f: F) -> std::result::Result<T, E>
+    where
+        F: FnOnce(&Transaction) -> std::result::Result<T, E>,
+        E: From<Error>,
+    {
+        self.writer()?.transaction(f)
+    }
+}
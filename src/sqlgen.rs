@@ -0,0 +1,536 @@
+use crate::aggregate::{Aggregate, Having};
+use crate::error::Result;
+use crate::query::{CompareOp, Condition, Order};
+use crate::value::Value;
+use std::convert::TryFrom;
+
+///
+/// Accumulates sql text together with the values bound to its `?` parameters.<br>
+/// All of rsorm's select, update and delete sql is written through this, so values are
+/// always bound as parameters and never written into the sql text.
+///
+#[derive(Default)]
+pub(crate) struct SqlWriter {
+    sql: String,
+    params: Vec<Value>,
+}
+
+impl SqlWriter {
+    pub(crate) fn new() -> Self {
+        SqlWriter::default()
+    }
+
+    pub(crate) fn push(&mut self, sql: &str) {
+        self.sql.push_str(sql);
+    }
+
+    pub(crate) fn bind(&mut self, value: Value) {
+        self.sql.push('?');
+        self.params.push(value);
+    }
+
+    /// sql already written with its own parameters
+    pub(crate) fn append(&mut self, sql: &str, params: &[Value]) {
+        self.sql.push_str(sql);
+        self.params.extend_from_slice(params);
+    }
+
+    pub(crate) fn finish(self) -> (String, Vec<Value>) {
+        (self.sql, self.params)
+    }
+
+    pub(crate) fn condition(&mut self, condition: &Condition) {
+        match condition {
+            Condition::Compare { column, op, value } => {
+                self.push(column);
+                self.push(match op {
+                    CompareOp::Eq => " = ",
+                    CompareOp::Ne => " != ",
+                    CompareOp::Lt => " < ",
+                    CompareOp::Le => " <= ",
+                    CompareOp::Gt => " > ",
+                    CompareOp::Ge => " >= ",
+                    CompareOp::Like => " LIKE ",
+                });
+                self.bind(value.clone());
+            }
+            Condition::IsNull { column, negated } => {
+                self.push(column);
+                self.push(if *negated { " IS NOT NULL" } else { " IS NULL" });
+            }
+            Condition::In {
+                column,
+                values,
+                negated,
+            } => {
+                if values.is_empty() {
+                    // nothing is in an empty list
+                    self.push(if *negated { "1" } else { "0" });
+                    return;
+                }
+                self.push(column);
+                self.push(if *negated { " NOT IN (" } else { " IN (" });
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        self.push(", ");
+                    }
+                    self.bind(value.clone());
+                }
+                self.push(")");
+            }
+            Condition::And(conditions) => self.join(conditions, " AND ", "1"),
+            Condition::Or(conditions) => self.join(conditions, " OR ", "0"),
+            Condition::Not(condition) => {
+                self.push("NOT ");
+                self.join(std::slice::from_ref(condition), "", "1");
+            }
+            // never run, a select with one is rejected by `check_values`
+            Condition::Unsupported { .. } => self.push("0"),
+        }
+    }
+
+    /// `(a <separator> b ...)`, or `empty` when there is nothing to join
+    fn join(&mut self, conditions: &[Condition], separator: &str, empty: &str) {
+        if conditions.is_empty() {
+            self.push(empty);
+            return;
+        }
+        self.push("(");
+        for (i, condition) in conditions.iter().enumerate() {
+            if i > 0 {
+                self.push(separator);
+            }
+            self.condition(condition);
+        }
+        self.push(")");
+    }
+
+    fn filter(&mut self, filter: &[Condition]) {
+        self.clause(" WHERE ", filter);
+    }
+
+    fn order<I: Iterator<Item = (String, Order)>>(&mut self, order: I) {
+        for (i, (column, order)) in order.enumerate() {
+            self.push(if i == 0 { " ORDER BY " } else { ", " });
+            self.push(&column);
+            self.push(match order {
+                Order::Asc => " ASC",
+                Order::Desc => " DESC",
+            });
+        }
+    }
+
+    /// `keyword` followed by `conditions`, which must all hold
+    fn clause(&mut self, keyword: &str, conditions: &[Condition]) {
+        match conditions {
+            [] => {}
+            [condition] => {
+                self.push(keyword);
+                self.condition(condition);
+            }
+            conditions => {
+                self.push(keyword);
+                self.join(conditions, " AND ", "1");
+            }
+        }
+    }
+}
+
+///
+/// A SELECT statement, built by `Query` and the select methods on `DB`.<br>
+/// Selects every column unless `columns` or `aggregates` name what to select.
+///
+#[derive(Clone, Debug)]
+pub(crate) struct Select {
+    pub(crate) table: String,
+    pub(crate) columns: Vec<String>,
+    pub(crate) aggregates: Vec<Aggregate>,
+    pub(crate) filter: Vec<Condition>,
+    pub(crate) group_by: Vec<String>,
+    pub(crate) having: Vec<Having>,
+    pub(crate) join: Option<JoinClause>,
+    pub(crate) with: Option<With>,
+    pub(crate) order: Vec<(String, Order)>,
+    pub(crate) limit: Option<u64>,
+    pub(crate) offset: Option<u64>,
+}
+
+impl Select {
+    pub(crate) fn new(table: &str) -> Self {
+        Select {
+            table: table.to_string(),
+            columns: Vec::new(),
+            aggregates: Vec::new(),
+            filter: Vec::new(),
+            group_by: Vec::new(),
+            having: Vec::new(),
+            join: None,
+            with: None,
+            order: Vec::new(),
+            limit: None,
+            offset: None,
+        }
+    }
+
+    pub(crate) fn to_sql(&self) -> (String, Vec<Value>) {
+        if let Some(join) = &self.join {
+            return self.join_sql(join);
+        }
+
+        let mut w = SqlWriter::new();
+        self.with_clause(&mut w);
+        w.push("SELECT ");
+        let results: Vec<String> = self
+            .columns
+            .iter()
+            .cloned()
+            .chain(self.aggregates.iter().map(Aggregate::to_sql))
+            .collect();
+        if results.is_empty() {
+            w.push("*");
+        } else {
+            w.push(&results.join(", "));
+        }
+        w.push(" FROM ");
+        w.push(self.source());
+        w.filter(&self.filter);
+
+        if !self.group_by.is_empty() {
+            w.push(" GROUP BY ");
+            w.push(&self.group_by.join(", "));
+        }
+        let having: Vec<Condition> = self.having.iter().map(|h| h.condition.clone()).collect();
+        w.clause(" HAVING ", &having);
+        w.order(
+            self.order
+                .iter()
+                .map(|(column, order)| (column.clone(), *order)),
+        );
+        self.page(&mut w);
+        w.finish()
+    }
+
+    /// the selected columns of both tables joined with `join`, with every column qualified
+    /// by its table
+    fn join_sql(&self, join: &JoinClause) -> (String, Vec<Value>) {
+        let mut w = SqlWriter::new();
+        self.with_clause(&mut w);
+        w.push("SELECT ");
+        w.push(&qualified_list("l", &self.columns));
+        w.push(", ");
+        w.push(&qualified_list("r", &join.columns));
+        w.push(" FROM ");
+        w.push(self.source());
+        w.push(if join.left {
+            " AS l LEFT JOIN "
+        } else {
+            " AS l INNER JOIN "
+        });
+        w.push(&join.table);
+        w.push(" AS r ON l.");
+        w.push(&join.on.0);
+        w.push(" = r.");
+        w.push(&join.on.1);
+
+        let filter: Vec<Condition> = self
+            .filter
+            .iter()
+            .map(|c| c.qualified("l"))
+            .chain(join.filter.iter().map(|c| c.qualified("r")))
+            .collect();
+        w.filter(&filter);
+        let qualify = |qualifier: &str, (column, order): &(String, Order)| {
+            (format!("{}.{}", qualifier, column), *order)
+        };
+        w.order(
+            self.order
+                .iter()
+                .map(|o| qualify("l", o))
+                .chain(join.order.iter().map(|o| qualify("r", o))),
+        );
+        self.page(&mut w);
+        w.finish()
+    }
+
+    /// the table the records are read from, the common table expression when there is one
+    fn source(&self) -> &str {
+        self.with.as_ref().map_or(&self.table, |with| &with.name)
+    }
+
+    fn with_clause(&self, w: &mut SqlWriter) {
+        if let Some(with) = &self.with {
+            w.push(if with.recursive {
+                "WITH RECURSIVE "
+            } else {
+                "WITH "
+            });
+            w.push(&with.name);
+            w.push(" AS (");
+            w.append(&with.sql, &with.params);
+            w.push(") ");
+        }
+    }
+
+    fn page(&self, w: &mut SqlWriter) {
+        match (self.limit, self.offset) {
+            (None, None) => {}
+            (limit, offset) => {
+                // sqlite only accepts OFFSET after a LIMIT, where -1 means no limit
+                w.push(" LIMIT ");
+                w.bind(Value::Integer(limit.map_or(-1, clamp)));
+                if let Some(offset) = offset {
+                    w.push(" OFFSET ");
+                    w.bind(Value::Integer(clamp(offset)));
+                }
+            }
+        }
+    }
+
+    /// the first value the statement can't bind, as an error
+    pub(crate) fn check_values(&self) -> Result<()> {
+        let joined = self.join.iter().flat_map(|join| join.filter.iter());
+        let with = self.with.iter().flat_map(|with| with.conditions.iter());
+        self.filter
+            .iter()
+            .chain(self.having.iter().map(|having| &having.condition))
+            .chain(joined)
+            .chain(with)
+            .try_for_each(Condition::check_values)
+    }
+
+    /// every column the statement refers to, for checking against the table's schema
+    pub(crate) fn columns(&self) -> Vec<String> {
+        let mut columns = self.columns.clone();
+        columns.extend(
+            self.aggregates
+                .iter()
+                .filter_map(|a| a.column().map(String::from)),
+        );
+        for condition in self.filter.iter() {
+            condition.collect_columns(&mut columns);
+        }
+        columns.extend(self.group_by.iter().cloned());
+        for having in self.having.iter() {
+            columns.extend(having.columns.iter().cloned());
+        }
+        if let Some(join) = &self.join {
+            columns.push(join.on.0.clone());
+        }
+        if let Some(with) = &self.with {
+            columns.extend(with.columns.iter().cloned());
+        }
+        columns.extend(self.order.iter().map(|(column, _)| column.clone()));
+        columns
+    }
+}
+
+/// `q.a, q.b, ...`, or every column `q.*` when `columns` is empty
+fn qualified_list(qualifier: &str, columns: &[String]) -> String {
+    if columns.is_empty() {
+        return format!("{}.*", qualifier);
+    }
+    columns
+        .iter()
+        .map(|column| format!("{}.{}", qualifier, column))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+///
+/// The table a `Select` is joined with, and the filters and ordering on its columns.
+///
+#[derive(Clone, Debug)]
+pub(crate) struct JoinClause {
+    pub(crate) table: String,
+    /// a LEFT JOIN, keeping the selected records that match nothing
+    pub(crate) left: bool,
+    /// the selected table's column and this table's column that must be equal
+    pub(crate) on: (String, String),
+    /// the columns of this table to select, after the selected table's columns
+    pub(crate) columns: Vec<String>,
+    pub(crate) filter: Vec<Condition>,
+    pub(crate) order: Vec<(String, Order)>,
+}
+
+impl JoinClause {
+    /// every column of the joined table the statement refers to
+    pub(crate) fn columns(&self) -> Vec<String> {
+        let mut columns = vec![self.on.1.clone()];
+        columns.extend(self.columns.iter().cloned());
+        for condition in self.filter.iter() {
+            condition.collect_columns(&mut columns);
+        }
+        columns.extend(self.order.iter().map(|(column, _)| column.clone()));
+        columns
+    }
+}
+
+/// a limit or offset as an sqlite integer, anything larger than `i64::MAX` is as good as it
+fn clamp(n: u64) -> i64 {
+    i64::try_from(n).unwrap_or(i64::MAX)
+}
+
+///
+/// A common table expression a `Select` reads from instead of its table.<br>
+/// Its records must have the table's columns, which the select's filters are checked against.
+///
+#[derive(Clone, Debug)]
+pub(crate) struct With {
+    pub(crate) recursive: bool,
+    pub(crate) name: String,
+    pub(crate) sql: String,
+    pub(crate) params: Vec<Value>,
+    /// the table columns the expression refers to
+    pub(crate) columns: Vec<String>,
+    /// the conditions written into `sql`, checked for values that couldn't be bound
+    pub(crate) conditions: Vec<Condition>,
+}
+
+/// the records of a tree stored in `table`, where `parent` holds the `key` of a record's parent
+pub(crate) fn tree(
+    table: &str,
+    parent: &str,
+    key: &str,
+    start: &Condition,
+    descendants: bool,
+) -> With {
+    // descendants have a parent that is the start or another descendant,
+    // and ancestors are the parent of the start or of another ancestor
+    let (down, up) = if descendants {
+        (parent, key)
+    } else {
+        (key, parent)
+    };
+    let mut w = SqlWriter::new();
+    w.push(&format!(
+        "SELECT * FROM {} WHERE {} IN (SELECT {} FROM {}",
+        table, down, up, table
+    ));
+    w.filter(std::slice::from_ref(start));
+    // UNION rather than UNION ALL, so a cycle ends once its records repeat
+    w.push(&format!(
+        ") UNION SELECT n.* FROM {} AS n INNER JOIN rsorm_tree AS t ON n.{} = t.{}",
+        table, down, up
+    ));
+    let (sql, params) = w.finish();
+
+    let mut columns = vec![parent.to_string(), key.to_string()];
+    start.collect_columns(&mut columns);
+    With {
+        recursive: true,
+        name: String::from("rsorm_tree"),
+        sql,
+        params,
+        columns,
+        conditions: vec![start.clone()],
+    }
+}
+
+/// deletes the records matching every condition of `filter`, or all of them when it's empty
+pub(crate) fn delete(table: &str, filter: &[Condition]) -> (String, Vec<Value>) {
+    let mut w = SqlWriter::new();
+    w.push("DELETE FROM ");
+    w.push(table);
+    w.filter(filter);
+    w.finish()
+}
+
+pub(crate) fn update(
+    table: &str,
+    set: &[(String, Value)],
+    filter: &Condition,
+) -> (String, Vec<Value>) {
+    let mut w = SqlWriter::new();
+    w.push("UPDATE ");
+    w.push(table);
+    for (i, (column, value)) in set.iter().enumerate() {
+        w.push(if i == 0 { " SET " } else { ", " });
+        w.push(column);
+        w.push(" = ");
+        w.bind(value.clone());
+    }
+    w.filter(std::slice::from_ref(filter));
+    w.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select() {
+        let mut select = Select::new("Person");
+        assert_eq!(select.to_sql().0, "SELECT * FROM Person");
+
+        select
+            .filter
+            .push(Condition::gt("age", 18).and(Condition::like("name", "J%")));
+        select
+            .filter
+            .push(Condition::is_null("birthday").or(Condition::in_("age", vec![1, 2])));
+        select.order.push((String::from("age"), Order::Desc));
+        select.order.push((String::from("name"), Order::Asc));
+        select.offset = Some(20);
+        let (sql, params) = select.to_sql();
+        assert_eq!(
+            sql,
+            "SELECT * FROM Person WHERE ((age > ? AND name LIKE ?) AND (birthday IS NULL OR age IN (?, ?))) \
+             ORDER BY age DESC, name ASC LIMIT ? OFFSET ?"
+        );
+        assert_eq!(
+            params,
+            vec![
+                Value::Integer(18),
+                Value::Text(String::from("J%")),
+                Value::Integer(1),
+                Value::Integer(2),
+                Value::Integer(-1),
+                Value::Integer(20),
+            ]
+        );
+        assert_eq!(
+            select.columns(),
+            vec!["age", "name", "birthday", "age", "age", "name"]
+        );
+    }
+
+    #[test]
+    fn test_select_aggregates() {
+        let mut select = Select::new("Person");
+        select.columns.push(String::from("city"));
+        select.aggregates.push(Aggregate::avg("age"));
+        select.filter.push(Condition::gt("age", 18));
+        select.group_by.push(String::from("city"));
+        select
+            .having
+            .push(Aggregate::count().gt(2).and(!Aggregate::max("age").lt(30)));
+        let (sql, params) = select.to_sql();
+        assert_eq!(
+            sql,
+            "SELECT city, AVG(age) FROM Person WHERE age > ? GROUP BY city \
+             HAVING (COUNT(*) > ? AND NOT (MAX(age) < ?))"
+        );
+        assert_eq!(
+            params,
+            vec![Value::Integer(18), Value::Integer(2), Value::Integer(30)]
+        );
+        assert_eq!(select.columns(), vec!["city", "age", "age", "city", "age"]);
+    }
+
+    #[test]
+    fn test_delete_and_update() {
+        let filter = Condition::from((
+            vec![String::from("name"), String::from("age")],
+            vec![String::from("'Jordan'"), String::from("8")],
+        ));
+        let (sql, params) = delete("Person", &[filter]);
+        assert_eq!(sql, "DELETE FROM Person WHERE (name = ? AND age = ?)");
+        assert_eq!(params.len(), 2);
+        assert_eq!(delete("Person", &[]).0, "DELETE FROM Person");
+
+        let set = vec![(String::from("age"), Value::Integer(9))];
+        let (sql, params) = update("Person", &set, &!Condition::in_("age", Vec::<i64>::new()));
+        assert_eq!(sql, "UPDATE Person SET age = ? WHERE NOT (0)");
+        assert_eq!(params, vec![Value::Integer(9)]);
+    }
+}
@@ -0,0 +1,83 @@
+use crate::config::DbConfig;
+use crate::error::Result;
+use crate::DB;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+///
+/// A database in a uniquely named temp file, deleted when dropped.<br>
+/// Each test can open its own, so tests that touch the database can run in parallel.
+/// Derefs to `DB`, so the full API is available on it.
+///
+pub struct TestDb {
+    db: Option<DB>,
+    path: PathBuf,
+}
+
+impl TestDb {
+    ///
+    /// Create an empty database in a new temp file
+    ///
+    pub fn new() -> Result<TestDb> {
+        TestDb::with_config(&DbConfig::default())
+    }
+
+    ///
+    /// Create an empty database in a new temp file, opened with connection settings
+    ///
+    /// # Arguments
+    /// * `config` - the settings to apply, see `DB::open_with`
+    ///
+    pub fn with_config(config: &DbConfig) -> Result<TestDb> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let name = format!(
+            "rsorm-test-{}-{}-{}.sq3",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            nanos
+        );
+        let path = std::env::temp_dir().join(name);
+        let db = DB::open_with(&path, config)?;
+        Ok(TestDb { db: Some(db), path })
+    }
+
+    ///
+    /// The path of the temp file, for opening more connections to the same database
+    ///
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Deref for TestDb {
+    type Target = DB;
+
+    fn deref(&self) -> &DB {
+        self.db.as_ref().expect("database is open until dropped")
+    }
+}
+
+impl DerefMut for TestDb {
+    fn deref_mut(&mut self) -> &mut DB {
+        self.db.as_mut().expect("database is open until dropped")
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        // the connection is closed first so sqlite is done with the files
+        self.db.take();
+        for suffix in ["", "-wal", "-shm", "-journal"].iter() {
+            let mut path = self.path.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
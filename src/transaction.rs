@@ -0,0 +1,116 @@
+use crate::error::{Error, Result};
+use crate::DB;
+use std::ops::Deref;
+
+///
+/// A transaction on a `DB`, started with `DB::begin` or `DB::transaction`.<br>
+/// Derefs to the `DB`, so the full insert/select/delete/update API is available on it.<br>
+/// Rolls back when dropped without calling `commit`, including while unwinding from a panic.<br>
+/// Beginning a transaction while another is open creates a SAVEPOINT nested inside it.<br>
+/// Finishing a transaction while one nested inside it is still open rolls both back
+/// and returns `Error::TransactionOrder`.
+///
+pub struct Transaction<'a> {
+    db: &'a DB,
+    depth: usize,
+    finished: bool,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn begin(db: &'a DB) -> Result<Transaction<'a>> {
+        let depth = db.tx_depth.get() + 1;
+        if depth == 1 {
+            // with a retry policy the write lock is taken up front, where waiting for it is safe
+            let begin = match db.retry.get() {
+                Some(_) => "BEGIN IMMEDIATE",
+                None => "BEGIN",
+            };
+            db.retrying(|| db.conn.execute(begin).map_err(Error::from))?;
+        } else {
+            db.conn.execute(format!("SAVEPOINT {}", savepoint(depth)))?;
+        }
+        db.tx_depth.set(depth);
+        Ok(Transaction {
+            db,
+            depth,
+            finished: false,
+        })
+    }
+
+    ///
+    /// Commit the transaction, or release the savepoint when nested
+    ///
+    pub fn commit(mut self) -> Result<()> {
+        self.finish(true)
+    }
+
+    ///
+    /// Roll back the transaction, or roll back to the savepoint when nested
+    ///
+    pub fn rollback(mut self) -> Result<()> {
+        self.finish(false)
+    }
+
+    ///
+    /// Whether this is a savepoint nested inside another transaction
+    ///
+    pub fn is_nested(&self) -> bool {
+        self.depth > 1
+    }
+
+    fn finish(&mut self, commit: bool) -> Result<()> {
+        self.finished = true;
+        let open = self.db.tx_depth.get();
+        if open < self.depth {
+            // an outer transaction finished first and already closed this one
+            return Err(Error::TransactionOrder(self.depth, open));
+        }
+        self.db.tx_depth.set(self.depth - 1);
+        if open > self.depth {
+            // nested transactions are still open, everything from this level down is undone
+            // so the connection is left consistent
+            let _ = self.close(false);
+            return Err(Error::TransactionOrder(self.depth, open));
+        }
+        self.close(commit)
+    }
+
+    fn close(&self, commit: bool) -> Result<()> {
+        let sql = match (self.depth, commit) {
+            (1, true) => {
+                let db = self.db;
+                let result = db.retrying(|| db.conn.execute("COMMIT").map_err(Error::from));
+                if result.is_err() {
+                    // a failed COMMIT leaves the transaction open, it is closed rather than leaked
+                    let _ = db.conn.execute("ROLLBACK");
+                }
+                return result;
+            }
+            (1, false) => String::from("ROLLBACK"),
+            (d, true) => format!("RELEASE {}", savepoint(d)),
+            (d, false) => format!("ROLLBACK TO {sp}; RELEASE {sp}", sp = savepoint(d)),
+        };
+        self.db.conn.execute(sql).map_err(Error::from)
+    }
+}
+
+impl<'a> Deref for Transaction<'a> {
+    type Target = DB;
+
+    fn deref(&self) -> &DB {
+        self.db
+    }
+}
+
+impl<'a> Drop for Transaction<'a> {
+    fn drop(&mut self) {
+        if !self.finished {
+            // nothing useful can be done with a failed rollback while dropping
+            let _ = self.finish(false);
+        }
+    }
+}
+
+fn savepoint(depth: usize) -> String {
+    format!("rsorm_savepoint_{}", depth)
+}
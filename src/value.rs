@@ -0,0 +1,306 @@
+use crate::error::Error;
+use std::convert::TryFrom;
+
+///
+/// A single sqlite value, one variant per sqlite storage class.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+    Null,
+}
+
+impl From<sqlite::Value> for Value {
+    fn from(v: sqlite::Value) -> Self {
+        match v {
+            sqlite::Value::Integer(i) => Value::Integer(i),
+            sqlite::Value::Float(f) => Value::Real(f),
+            sqlite::Value::String(s) => Value::Text(s),
+            sqlite::Value::Binary(b) => Value::Blob(b),
+            sqlite::Value::Null => Value::Null,
+        }
+    }
+}
+
+impl Value {
+    /// the value of a literal written with the `sql` macro, such as `'Gresham'`, `100000` or `44.3`
+    pub(crate) fn from_literal(literal: &str) -> Value {
+        let quoted = literal.len() >= 2 && literal.starts_with('\'') && literal.ends_with('\'');
+        if quoted {
+            return Value::Text(literal[1..literal.len() - 1].replace("''", "'"));
+        }
+        if literal.eq_ignore_ascii_case("null") {
+            return Value::Null;
+        }
+        if let Ok(i) = literal.parse::<i64>() {
+            return Value::Integer(i);
+        }
+        match literal.parse::<f64>() {
+            Ok(f) => Value::Real(f),
+            Err(_) => Value::Text(literal.to_string()),
+        }
+    }
+}
+
+///
+/// Conversion of a rust value into a `Value` that can be bound to a statement.<br>
+/// Implemented for the primitive types rsorm models are built from.
+/// Fails with `Error::UnsupportedType` for a value sqlite can't store,
+/// such as a `u64` larger than `i64::MAX`.
+///
+pub trait ToValue {
+    fn to_value(&self) -> Result<Value, Error>;
+}
+
+///
+/// Conversion of a `Value` read from a row back into a rust value.<br>
+/// Follows sqlite's lenient typing: integers are accepted for floats and numbers for strings.
+///
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> Result<Self, Error>;
+}
+
+macro_rules! integer_value {
+    ($($t:ty)*) => {
+        $(
+            impl ToValue for $t {
+                fn to_value(&self) -> Result<Value, Error> {
+                    i64::try_from(*self)
+                        .map(Value::Integer)
+                        .map_err(|_| Error::UnsupportedType {
+                            column: String::new(),
+                            ty: format!("{} larger than i64::MAX", stringify!($t)),
+                        })
+                }
+            }
+
+            impl FromValue for $t {
+                fn from_value(value: Value) -> Result<Self, Error> {
+                    match value {
+                        Value::Integer(i) => <$t>::try_from(i).map_err(|_| {
+                            Error::Decode(format!("{} is out of range for {}", i, stringify!($t)))
+                        }),
+                        other => Err(Error::Decode(format!(
+                            "expected an integer for {}, found {:?}",
+                            stringify!($t),
+                            other
+                        ))),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+integer_value!(i8 i16 i32 i64 u8 u16 u32 u64);
+
+impl ToValue for f64 {
+    fn to_value(&self) -> Result<Value, Error> {
+        Ok(Value::Real(*self))
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::Real(f) => Ok(f),
+            Value::Integer(i) => Ok(i as f64),
+            other => Err(Error::Decode(format!(
+                "expected a real for f64, found {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+impl ToValue for f32 {
+    fn to_value(&self) -> Result<Value, Error> {
+        Ok(Value::Real(f64::from(*self)))
+    }
+}
+
+impl FromValue for f32 {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        f64::from_value(value).map(|f| f as f32)
+    }
+}
+
+impl ToValue for bool {
+    fn to_value(&self) -> Result<Value, Error> {
+        Ok(Value::Integer(i64::from(*self)))
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        i64::from_value(value).map(|i| i != 0)
+    }
+}
+
+impl ToValue for String {
+    fn to_value(&self) -> Result<Value, Error> {
+        Ok(Value::Text(self.clone()))
+    }
+}
+
+impl ToValue for str {
+    fn to_value(&self) -> Result<Value, Error> {
+        Ok(Value::Text(self.to_string()))
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::Text(s) => Ok(s),
+            Value::Integer(i) => Ok(i.to_string()),
+            Value::Real(f) => Ok(f.to_string()),
+            other => Err(Error::Decode(format!(
+                "expected text for String, found {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+impl ToValue for Vec<u8> {
+    fn to_value(&self) -> Result<Value, Error> {
+        Ok(Value::Blob(self.clone()))
+    }
+}
+
+impl FromValue for Vec<u8> {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::Blob(b) => Ok(b),
+            Value::Text(s) => Ok(s.into_bytes()),
+            other => Err(Error::Decode(format!(
+                "expected a blob for Vec<u8>, found {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value(&self) -> Result<Value, Error> {
+        match self {
+            Some(v) => v.to_value(),
+            None => Ok(Value::Null),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        match value {
+            Value::Null => Ok(None),
+            other => T::from_value(other).map(Some),
+        }
+    }
+}
+
+impl<T: ToValue + ?Sized> ToValue for &T {
+    fn to_value(&self) -> Result<Value, Error> {
+        (**self).to_value()
+    }
+}
+
+impl ToValue for Value {
+    fn to_value(&self) -> Result<Value, Error> {
+        Ok(self.clone())
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Self, Error> {
+        Ok(value)
+    }
+}
+
+/// converts the parameters of a statement written in sql, naming the one that can't be stored
+pub(crate) fn param_values(params: &[&dyn ToValue]) -> Result<Vec<Value>, Error> {
+    params
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            p.to_value()
+                .map_err(|e| e.in_column(&format!("parameter {}", i + 1)))
+        })
+        .collect()
+}
+
+/// binds `values` to the statement's parameters, the leftmost parameter is bound first
+pub(crate) fn bind_values(stmt: &mut sqlite::Statement, values: &[Value]) -> sqlite::Result<()> {
+    for (i, value) in values.iter().enumerate() {
+        match value {
+            Value::Integer(v) => stmt.bind(i + 1, *v)?,
+            Value::Real(v) => stmt.bind(i + 1, *v)?,
+            Value::Text(v) => stmt.bind(i + 1, v.as_str())?,
+            Value::Blob(v) => stmt.bind(i + 1, v.as_slice())?,
+            Value::Null => stmt.bind(i + 1, ())?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_literal() {
+        assert_eq!(
+            Value::from_literal("'Gresham'"),
+            Value::Text(String::from("Gresham"))
+        );
+        assert_eq!(
+            Value::from_literal("'it''s'"),
+            Value::Text(String::from("it's"))
+        );
+        assert_eq!(Value::from_literal("100000"), Value::Integer(100000));
+        assert_eq!(Value::from_literal("44.3"), Value::Real(44.3));
+        assert_eq!(Value::from_literal("NULL"), Value::Null);
+    }
+
+    #[test]
+    fn test_integer_range() {
+        assert_eq!(
+            (i64::MAX as u64).to_value().unwrap(),
+            Value::Integer(i64::MAX)
+        );
+        assert!(matches!(
+            (i64::MAX as u64 + 1).to_value(),
+            Err(Error::UnsupportedType { .. })
+        ));
+        assert!(matches!(
+            u64::MAX.to_value(),
+            Err(Error::UnsupportedType { .. })
+        ));
+
+        assert_eq!(u8::from_value(Value::Integer(255)).unwrap(), 255);
+        assert!(matches!(
+            u8::from_value(Value::Integer(256)),
+            Err(Error::Decode(_))
+        ));
+        assert!(matches!(
+            u16::from_value(Value::Integer(-1)),
+            Err(Error::Decode(_))
+        ));
+        assert!(matches!(
+            u32::from_value(Value::Integer(i64::from(u32::MAX) + 1)),
+            Err(Error::Decode(_))
+        ));
+        assert!(matches!(
+            u64::from_value(Value::Integer(-1)),
+            Err(Error::Decode(_))
+        ));
+        assert!(matches!(
+            i8::from_value(Value::Integer(-129)),
+            Err(Error::Decode(_))
+        ));
+    }
+}